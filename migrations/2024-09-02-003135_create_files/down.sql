@@ -1 +1 @@
-DROP TABLE files;
\ No newline at end of file
+DROP TABLE IF EXISTS files;
@@ -0,0 +1,12 @@
+DROP INDEX IF EXISTS files_target_format_idx;
+DROP INDEX IF EXISTS files_created_at_idx;
+
+ALTER TABLE files
+    DROP COLUMN created_at,
+    DROP COLUMN size_bytes,
+    DROP COLUMN mime_type,
+    DROP COLUMN source_file_name,
+    DROP COLUMN source_format,
+    DROP COLUMN target_format,
+    DROP COLUMN backend_job_id,
+    DROP COLUMN sha256;
@@ -0,0 +1,25 @@
+ALTER TABLE files
+    ADD COLUMN created_at       TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    ADD COLUMN size_bytes       BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN mime_type        VARCHAR NOT NULL DEFAULT 'application/octet-stream',
+    ADD COLUMN source_file_name VARCHAR,
+    ADD COLUMN source_format    VARCHAR,
+    ADD COLUMN target_format    VARCHAR NOT NULL DEFAULT '',
+    ADD COLUMN backend_job_id   VARCHAR,
+    ADD COLUMN sha256           VARCHAR NOT NULL DEFAULT '';
+
+-- Backfill what can be derived from rows stored before these columns existed.
+UPDATE files SET
+    size_bytes    = octet_length(decode(content, 'base64')),
+    sha256        = encode(sha256(decode(content, 'base64')), 'hex'),
+    target_format = COALESCE(lower(substring(file_name FROM '\.([^.]+)$')), '');
+
+UPDATE files SET mime_type = CASE target_format
+    WHEN 'pdf'  THEN 'application/pdf'
+    WHEN 'docx' THEN 'application/vnd.openxmlformats-officedocument.wordprocessingml.document'
+    WHEN 'pptx' THEN 'application/vnd.openxmlformats-officedocument.presentationml.presentation'
+    ELSE mime_type
+END;
+
+CREATE INDEX files_created_at_idx ON files (created_at);
+CREATE INDEX files_target_format_idx ON files (target_format);
@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS files_cache_key_idx;
+
+ALTER TABLE files DROP COLUMN cache_key;
@@ -0,0 +1,5 @@
+-- Hash of the source bytes, target format and conversion options that
+-- produced the file, used to serve repeated conversions from the database.
+ALTER TABLE files ADD COLUMN cache_key VARCHAR;
+
+CREATE INDEX files_cache_key_idx ON files (cache_key);
@@ -0,0 +1,6 @@
+DROP INDEX IF EXISTS files_file_name_trgm_idx;
+DROP INDEX IF EXISTS files_file_name_tsv_idx;
+
+ALTER TABLE files DROP COLUMN file_name_tsv;
+
+DROP EXTENSION IF EXISTS pg_trgm;
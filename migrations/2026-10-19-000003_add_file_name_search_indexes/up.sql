@@ -0,0 +1,11 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- Separators are replaced with spaces so `lesson_3-notes.pdf` is indexed as
+-- the words `lesson`, `3`, `notes` and `pdf`.
+ALTER TABLE files ADD COLUMN file_name_tsv TSVECTOR NOT NULL
+    GENERATED ALWAYS AS (to_tsvector('simple', regexp_replace(file_name, '[_.\-]+', ' ', 'g'))) STORED;
+
+CREATE INDEX files_file_name_tsv_idx ON files USING GIN (file_name_tsv);
+
+-- Serves trigram similarity as well as the ILIKE substring fallback.
+CREATE INDEX files_file_name_trgm_idx ON files USING GIN (file_name gin_trgm_ops);
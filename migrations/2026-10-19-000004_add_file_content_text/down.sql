@@ -0,0 +1,5 @@
+DROP INDEX IF EXISTS files_content_tsv_idx;
+
+ALTER TABLE files
+    DROP COLUMN content_tsv,
+    DROP COLUMN content_text;
@@ -0,0 +1,8 @@
+-- Plain text of the converted document (or of its source when the result
+-- holds none), so files can be found by what they say.
+ALTER TABLE files ADD COLUMN content_text TEXT;
+
+ALTER TABLE files ADD COLUMN content_tsv TSVECTOR NOT NULL
+    GENERATED ALWAYS AS (to_tsvector('english', COALESCE(content_text, ''))) STORED;
+
+CREATE INDEX files_content_tsv_idx ON files USING GIN (content_tsv);
@@ -0,0 +1,3 @@
+ALTER TABLE files
+    DROP COLUMN has_preview,
+    DROP COLUMN preview;
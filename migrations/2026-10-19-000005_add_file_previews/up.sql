@@ -0,0 +1,5 @@
+-- Base64 encoded PNG thumbnail of the converted file's first page.
+ALTER TABLE files ADD COLUMN preview TEXT;
+
+ALTER TABLE files ADD COLUMN has_preview BOOLEAN NOT NULL
+    GENERATED ALWAYS AS (preview IS NOT NULL) STORED;
@@ -0,0 +1,14 @@
+CREATE TABLE jobs (
+    id SERIAL PRIMARY KEY,
+    session_id VARCHAR NOT NULL,
+    backend_job_id VARCHAR UNIQUE,
+    source_file_name VARCHAR NOT NULL,
+    target_format VARCHAR NOT NULL,
+    status VARCHAR NOT NULL DEFAULT 'queued',
+    file_id INTEGER REFERENCES files (id) ON DELETE SET NULL,
+    error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX jobs_session_id_idx ON jobs (session_id, created_at DESC);
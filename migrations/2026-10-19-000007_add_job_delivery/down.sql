@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS jobs_undelivered_idx;
+ALTER TABLE jobs DROP COLUMN IF EXISTS notified_at;
+ALTER TABLE jobs DROP COLUMN IF EXISTS cache_key;
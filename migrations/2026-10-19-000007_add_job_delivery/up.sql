@@ -0,0 +1,7 @@
+-- Lets a job be picked up again after a restart, and remembers whether the
+-- session has been told how it ended.
+ALTER TABLE jobs ADD COLUMN cache_key VARCHAR;
+ALTER TABLE jobs ADD COLUMN notified_at TIMESTAMPTZ;
+
+CREATE INDEX jobs_undelivered_idx ON jobs (session_id)
+    WHERE notified_at IS NULL AND status IN ('finished', 'failed');
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS files_backend_job_id_idx;
+DROP TABLE IF EXISTS webhook_deliveries;
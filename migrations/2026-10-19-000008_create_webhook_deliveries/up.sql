@@ -0,0 +1,12 @@
+-- One row per webhook we accepted, so retried deliveries are recognised.
+CREATE TABLE webhook_deliveries (
+    backend_job_id VARCHAR NOT NULL,
+    event VARCHAR NOT NULL,
+    received_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    processed_at TIMESTAMPTZ,
+    file_id INTEGER REFERENCES files (id) ON DELETE SET NULL,
+    PRIMARY KEY (backend_job_id, event)
+);
+
+-- Lets a replayed delivery find the file its first delivery stored.
+CREATE INDEX files_backend_job_id_idx ON files (backend_job_id);
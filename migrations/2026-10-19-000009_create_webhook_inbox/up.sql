@@ -0,0 +1,15 @@
+-- Every webhook request as it arrived, so failed ones can be inspected and
+-- replayed.
+CREATE TABLE webhook_inbox (
+    id SERIAL PRIMARY KEY,
+    endpoint VARCHAR NOT NULL,
+    headers JSONB NOT NULL,
+    body TEXT NOT NULL,
+    status VARCHAR NOT NULL DEFAULT 'received',
+    error TEXT,
+    attempts INTEGER NOT NULL DEFAULT 1,
+    received_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    processed_at TIMESTAMPTZ
+);
+
+CREATE INDEX webhook_inbox_status_idx ON webhook_inbox (status, received_at DESC);
@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS jobs_undelivered_idx;
+CREATE INDEX jobs_undelivered_idx ON jobs (session_id)
+    WHERE notified_at IS NULL AND status IN ('finished', 'failed');
@@ -0,0 +1,3 @@
+ALTER TABLE jobs DROP COLUMN IF EXISTS has_input;
+ALTER TABLE jobs DROP COLUMN IF EXISTS attempts;
+ALTER TABLE jobs DROP COLUMN IF EXISTS input;
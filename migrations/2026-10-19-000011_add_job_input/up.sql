@@ -0,0 +1,7 @@
+-- The upload, base64 encoded, kept until the job finishes so a failed job
+-- can be submitted again.
+ALTER TABLE jobs ADD COLUMN input TEXT;
+ALTER TABLE jobs ADD COLUMN attempts INTEGER NOT NULL DEFAULT 1;
+
+ALTER TABLE jobs ADD COLUMN has_input BOOLEAN NOT NULL
+    GENERATED ALWAYS AS (input IS NOT NULL) STORED;
@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS jobs_processing_idx;
+DROP INDEX IF EXISTS jobs_queued_idx;
+ALTER TABLE jobs DROP COLUMN IF EXISTS priority;
@@ -0,0 +1,5 @@
+-- Queued jobs are submitted highest priority first, see `converter::queue`.
+ALTER TABLE jobs ADD COLUMN priority SMALLINT NOT NULL DEFAULT 0;
+
+CREATE INDEX jobs_queued_idx ON jobs (priority DESC, created_at) WHERE status = 'queued';
+CREATE INDEX jobs_processing_idx ON jobs (session_id) WHERE status = 'processing';
@@ -0,0 +1,4 @@
+-- Credits CloudConvert charged for the job, summed over its tasks.
+ALTER TABLE jobs ADD COLUMN credits INTEGER;
+
+CREATE INDEX jobs_updated_at_idx ON jobs (updated_at DESC) WHERE credits IS NOT NULL;
@@ -1 +1,9 @@
+pub mod backend;
+pub mod cache;
+pub mod credits;
+pub mod formats;
 pub mod jobs;
+pub mod queue;
+pub mod retry;
+pub mod submit;
+pub mod text;
@@ -0,0 +1,125 @@
+use std::env;
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use tracing::{debug, info};
+
+use super::retry;
+use crate::metrics::Metrics;
+
+/// The CloudConvert API to talk to, the sandbox when `DEV_MODE` is set.
+pub fn base_url() -> String {
+    let dev_mode = env::var("DEV_MODE")
+        .expect("DEV_MODE must be set! Check your .env file!")
+        .parse::<bool>()
+        .expect("DEV_MODE must be true/false! Check your .env file!");
+
+    match dev_mode {
+        false => env::var("CLOUDCONVERT_API").expect("CLOUDCONVERT_API must be set! Check your .env file!"),
+        true  => env::var("CLOUDCONVERT_SANDBOX_API").expect("CLOUDCONVERT_SANDBOX_API must be set! Check your .env file!") 
+    }
+}
+
+pub fn api_key() -> String {
+    env::var("API_KEY")
+        .expect("API_KEY must be set! Check your .env file!")
+}
+
+#[derive(Deserialize)]
+struct UserResponse {
+    data: UserData
+}
+
+#[derive(Deserialize)]
+struct UserData {
+    credits: i64
+}
+
+/// The credits left on the account behind `API_KEY`.
+pub async fn credits(metrics: &Metrics) -> Result<i64> {
+    let request = reqwest::Client::new()
+        .get(format!("{}/v2/users/me", base_url()))
+        .bearer_auth(api_key());
+
+    let user = retry::send(metrics, "credits", request, "Credit check")
+        .await?
+        .error_for_status()?
+        .json::<UserResponse>()
+        .await?;
+
+    Ok(user.data.credits)
+}
+
+#[derive(Deserialize)]
+struct JobListResponse {
+    data: Vec<JobSummary>
+}
+
+#[derive(Deserialize)]
+struct JobSummary {
+    id: String
+}
+
+/// The id of the job created with `tag`, if CloudConvert has one.
+pub async fn find_job(metrics: &Metrics, tag: &str) -> Result<Option<String>> {
+    let request = reqwest::Client::new()
+        .get(format!("{}/v2/jobs", base_url()))
+        .query(&[("filter[tag]", tag)])
+        .bearer_auth(api_key());
+
+    let jobs = retry::send(metrics, "lookup", request, &format!("Lookup of job {}", tag))
+        .await?
+        .error_for_status()?
+        .json::<JobListResponse>()
+        .await?;
+
+    Ok(jobs.data.into_iter().next().map(|job| job.id))
+}
+
+#[derive(Deserialize)]
+struct JobResponse {
+    data: JobData
+}
+
+#[derive(Deserialize)]
+struct JobData {
+    tasks: Vec<TaskStatus>
+}
+
+#[derive(Deserialize)]
+struct TaskStatus {
+    id: String,
+    status: String
+}
+
+/// Cancels every task of the job that hasn't ended yet. CloudConvert only
+/// cancels tasks, a job ends once none of its tasks are left running.
+pub async fn cancel_job(backend_job_id: &str) -> Result<()> {
+    let client = reqwest::Client::new();
+    let base_url = base_url();
+    let api_key = api_key();
+
+    let job = client.get(format!("{}/v2/jobs/{}", base_url, backend_job_id))
+        .bearer_auth(&api_key)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<JobResponse>()
+        .await?;
+
+    let running = job.data.tasks.iter()
+        .filter(|task| task.status == "waiting" || task.status == "processing");
+
+    for task in running {
+        debug!("[Job {}] Cancelling task {}", backend_job_id, task.id);
+        client.post(format!("{}/v2/tasks/{}/cancel", base_url, task.id))
+            .bearer_auth(&api_key)
+            .send()
+            .await?
+            .error_for_status()
+            .map_err(|err| anyhow!("Unable to cancel task {}: {}", task.id, err))?;
+    }
+
+    info!("[Job {}] Cancelled at CloudConvert!", backend_job_id);
+    Ok(())
+}
@@ -0,0 +1,54 @@
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, QueryResult};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::database::{retention, schema::files::dsl::{files, id, cache_key as file_cache_key, created_at}};
+
+/// Key identifying a conversion: the source bytes, the requested format and
+/// the options passed to the convert task.
+pub fn cache_key(input: &[u8], target_format: &str, options: &Value) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(input);
+    hasher.update([0]);
+    hasher.update(target_format.as_bytes());
+    hasher.update([0]);
+    hasher.update(options.to_string().as_bytes());
+
+    format!("{:x}", hasher.finalize())
+}
+
+/// Finds the newest unexpired file produced by the conversion `key`.
+pub async fn find(conn: &mut AsyncPgConnection, key: &str) -> QueryResult<Option<i32>> {
+    files
+        .filter(file_cache_key.eq(key))
+        .filter(created_at.gt(retention::cutoff()))
+        .order(created_at.desc())
+        .select(id)
+        .first::<i32>(conn)
+        .await
+        .optional()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::cache_key;
+
+    #[test]
+    fn keys_depend_on_every_part() {
+        let key = cache_key(b"input", "pdf", &json!({}));
+        assert_eq!(key.len(), 64);
+        assert_eq!(key, cache_key(b"input", "pdf", &json!({})));
+
+        assert_ne!(key, cache_key(b"other", "pdf", &json!({})));
+        assert_ne!(key, cache_key(b"input", "docx", &json!({})));
+        assert_ne!(key, cache_key(b"input", "pdf", &json!({ "pages": "1" })));
+    }
+
+    #[test]
+    fn parts_cannot_run_into_each_other() {
+        assert_ne!(cache_key(b"inputp", "df", &json!({})), cache_key(b"input", "pdf", &json!({})));
+    }
+}
@@ -0,0 +1,158 @@
+use std::{env, fmt, time::Duration};
+
+use chrono::{DateTime, Utc};
+use tokio::sync::RwLock;
+use tracing::{error, info, warn};
+
+use crate::{converter::backend, metrics::Metrics, SharedState, State};
+
+/// Below how many credits new jobs are held back by default.
+const DEFAULT_THRESHOLD: i64 = 10;
+
+/// How often the balance is checked by default.
+const DEFAULT_CHECK_INTERVAL_SECONDS: u64 = 300;
+
+/// What happens to uploads while credits are low.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LowCreditAction {
+    /// Accept them, but only submit them once credits are topped up.
+    Queue,
+    /// Turn them away with a message.
+    Refuse
+}
+
+impl LowCreditAction {
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LowCreditAction::Queue => "queue",
+            LowCreditAction::Refuse => "refuse"
+        }
+    }
+
+    pub fn parse(action: &str) -> Option<LowCreditAction> {
+        match action {
+            "queue" => Some(LowCreditAction::Queue),
+            "refuse" => Some(LowCreditAction::Refuse),
+            _ => None
+        }
+    }
+
+}
+
+impl fmt::Display for LowCreditAction {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+
+}
+
+#[derive(Clone, Copy)]
+pub struct Reading {
+    pub remaining: i64,
+    pub checked_at: DateTime<Utc>
+}
+
+impl Reading {
+
+    pub fn display_checked_at(&self) -> String {
+        self.checked_at.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+    }
+
+}
+
+/// The CloudConvert balance as last seen, and what to do when it runs low.
+pub struct Credits {
+    reading: RwLock<Option<Reading>>,
+    pub threshold: i64,
+    pub action: LowCreditAction
+}
+
+impl Credits {
+
+    /// Configured by `CREDITS_THRESHOLD` and `LOW_CREDITS_ACTION`.
+    pub fn from_env() -> Self {
+        let threshold = env::var("CREDITS_THRESHOLD")
+            .ok()
+            .and_then(|threshold| threshold.parse().ok())
+            .unwrap_or(DEFAULT_THRESHOLD);
+
+        let action = env::var("LOW_CREDITS_ACTION")
+            .ok()
+            .and_then(|action| LowCreditAction::parse(&action))
+            .unwrap_or(LowCreditAction::Queue);
+
+        Credits {
+            reading: RwLock::new(None),
+            threshold,
+            action
+        }
+    }
+
+    pub async fn reading(&self) -> Option<Reading> {
+        *self.reading.read().await
+    }
+
+    /// Whether the last known balance is below the threshold. An unknown
+    /// balance never holds jobs back.
+    pub async fn is_low(&self) -> bool {
+        self.reading.read().await.is_some_and(|reading| reading.remaining < self.threshold)
+    }
+
+    /// Takes credits a finished job was charged off the last reading, so the
+    /// guard reacts before the next check.
+    pub async fn consume(&self, credits: i64) {
+        if let Some(reading) = self.reading.write().await.as_mut() {
+            reading.remaining -= credits;
+        }
+    }
+
+}
+
+/// Checks the balance every `CREDITS_CHECK_INTERVAL_SECONDS`.
+pub async fn start_monitor(state: SharedState) {
+    let interval = env::var("CREDITS_CHECK_INTERVAL_SECONDS")
+        .ok()
+        .and_then(|interval| interval.parse().ok())
+        .filter(|interval| *interval > 0)
+        .unwrap_or(DEFAULT_CHECK_INTERVAL_SECONDS);
+
+    info!("Checking CloudConvert credits every {}s", interval);
+    tokio::spawn(async move {
+        loop {
+            check(&state).await;
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+        }
+    });
+}
+
+/// Asks CloudConvert for the balance and records it, returning it if the
+/// check worked.
+pub async fn check(state: &State) -> Option<Reading> {
+    let remaining = match backend::credits(&state.metrics).await {
+        Ok(remaining) => remaining,
+        Err(err) => {
+            error!("Unable to check CloudConvert credits: {}", err);
+            return None
+        }
+    };
+
+    let was_low = state.credits.is_low().await;
+    let reading = Reading {
+        remaining,
+        checked_at: Utc::now()
+    };
+
+    *state.credits.reading.write().await = Some(reading);
+    Metrics::set(&state.metrics.credits_remaining, remaining);
+
+    if remaining < state.credits.threshold {
+        warn!("Only {} CloudConvert credits left, holding back new jobs!", remaining);
+    } else if was_low {
+        info!("CloudConvert credits are back at {}, submitting jobs again", remaining);
+        state.submissions.wake();
+    }
+
+    Some(reading)
+}
@@ -0,0 +1,240 @@
+
+/// Largest image we accept for conversion.
+const MAX_IMAGE_SIZE: usize = 10 * 1024 * 1024;
+
+/// Largest document we accept for conversion.
+const MAX_DOCUMENT_SIZE: usize = 20 * 1024 * 1024;
+
+/// The content signature an upload has to start with.
+#[derive(PartialEq, Clone, Copy)]
+enum Signature {
+    Pdf,
+    Png,
+    Jpeg,
+    /// Office Open XML documents are zip archives.
+    Zip,
+    /// Legacy Office documents are OLE2 compound files.
+    Ole
+}
+
+/// A file type that may be uploaded for conversion.
+pub struct UploadFormat {
+    pub extension: &'static str,
+    pub max_size: usize,
+    signature: Signature,
+    /// Marker that has to appear somewhere in the content, used to tell apart
+    /// formats that share a container (e.g. `.docx` and `.pptx`).
+    marker: Option<&'static [u8]>
+}
+
+pub const UPLOAD_FORMATS: [UploadFormat; 8] = [
+    UploadFormat { extension: "jpg", max_size: MAX_IMAGE_SIZE, signature: Signature::Jpeg, marker: None },
+    UploadFormat { extension: "jpeg", max_size: MAX_IMAGE_SIZE, signature: Signature::Jpeg, marker: None },
+    UploadFormat { extension: "png", max_size: MAX_IMAGE_SIZE, signature: Signature::Png, marker: None },
+    UploadFormat { extension: "ppt", max_size: MAX_DOCUMENT_SIZE, signature: Signature::Ole, marker: Some(b"P\0o\0w\0e\0r\0P\0o\0i\0n\0t\0") },
+    UploadFormat { extension: "pptx", max_size: MAX_DOCUMENT_SIZE, signature: Signature::Zip, marker: Some(b"ppt/") },
+    UploadFormat { extension: "doc", max_size: MAX_DOCUMENT_SIZE, signature: Signature::Ole, marker: Some(b"W\0o\0r\0d\0D\0o\0c\0u\0m\0e\0n\0t\0") },
+    UploadFormat { extension: "docx", max_size: MAX_DOCUMENT_SIZE, signature: Signature::Zip, marker: Some(b"word/") },
+    UploadFormat { extension: "pdf", max_size: MAX_DOCUMENT_SIZE, signature: Signature::Pdf, marker: None },
+];
+
+/// Formats uploads can be converted to.
+pub const TARGET_FORMATS: [&str; 3] = ["pdf", "docx", "pptx"];
+
+/// Number of leading bytes needed to recognise every signature.
+pub const SIGNATURE_LENGTH: usize = 8;
+
+pub enum UploadError {
+    /// The claimed extension is not one we convert.
+    UnsupportedExtension(String),
+    /// The content does not match the claimed extension.
+    SignatureMismatch(&'static str),
+    /// The upload is larger than its format allows.
+    TooLarge(&'static str, usize)
+}
+
+impl UploadError {
+
+    pub fn message(&self) -> String {
+        match self {
+            UploadError::UnsupportedExtension(extension) if extension.is_empty() => {
+                "Files without an extension can't be converted!".to_string()
+            },
+            UploadError::UnsupportedExtension(extension) => {
+                format!(".{} files can't be converted!", extension)
+            },
+            UploadError::SignatureMismatch(extension) => {
+                format!("That file doesn't look like a real .{} file!", extension)
+            },
+            UploadError::TooLarge(extension, max_size) => {
+                format!(".{} files can be at most {} MB!", extension, max_size / (1024 * 1024))
+            }
+        }
+    }
+
+}
+
+/// Every accepted extension with a leading dot, for the HTML `accept` attribute.
+pub fn authorized_extensions() -> Vec<String> {
+    UPLOAD_FORMATS.iter()
+        .map(|format| format!(".{}", format.extension))
+        .collect()
+}
+
+/// Whether uploads can be converted to `target_format`.
+pub fn is_target_format(target_format: &str) -> bool {
+    TARGET_FORMATS.contains(&target_format)
+}
+
+/// Looks up the upload format for the extension of `file_name`.
+pub fn upload_format(file_name: &str) -> Result<&'static UploadFormat, UploadError> {
+    let extension = extension(file_name);
+    UPLOAD_FORMATS.iter()
+        .find(|format| format.extension == extension)
+        .ok_or(UploadError::UnsupportedExtension(extension))
+}
+
+impl UploadFormat {
+
+    /// Checks the first bytes of an upload against this format's signature.
+    pub fn check_signature(&self, head: &[u8]) -> Result<(), UploadError> {
+        if sniff(head) == Some(self.signature) {
+            Ok(())
+        } else {
+            Err(UploadError::SignatureMismatch(self.extension))
+        }
+    }
+
+    pub fn check_size(&self, size: usize) -> Result<(), UploadError> {
+        if size > self.max_size {
+            Err(UploadError::TooLarge(self.extension, self.max_size))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Checks the complete upload, including the container marker.
+    pub fn check_content(&self, bytes: &[u8]) -> Result<(), UploadError> {
+        self.check_size(bytes.len())?;
+        self.check_signature(bytes)?;
+
+        match self.marker {
+            Some(marker) if !contains(bytes, marker) => Err(UploadError::SignatureMismatch(self.extension)),
+            _ => Ok(())
+        }
+    }
+
+}
+
+fn sniff(head: &[u8]) -> Option<Signature> {
+    if head.starts_with(b"%PDF-") {
+        Some(Signature::Pdf)
+    } else if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some(Signature::Png)
+    } else if head.starts_with(b"\xff\xd8\xff") {
+        Some(Signature::Jpeg)
+    } else if head.starts_with(b"PK\x03\x04") {
+        Some(Signature::Zip)
+    } else if head.starts_with(b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1") {
+        Some(Signature::Ole)
+    } else {
+        None
+    }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+/// Lowercased extension of `file_name` without the leading dot, or an empty
+/// string when the name has none.
+pub fn extension(file_name: &str) -> String {
+    match file_name.rfind('.') {
+        Some(index) => file_name[(index + 1)..].to_lowercase(),
+        None => String::new()
+    }
+}
+
+pub fn mime_type(extension: &str) -> &'static str {
+    match extension {
+        "pdf" => "application/pdf",
+        "doc" => "application/msword",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "ppt" => "application/vnd.ms-powerpoint",
+        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        _ => "application/octet-stream"
+    }
+}
+
+/// Whether browsers can display files of this MIME type themselves.
+pub fn is_viewable(mime_type: &str) -> bool {
+    mime_type == "application/pdf" || mime_type.starts_with("image/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{extension, is_target_format, sniff, upload_format, Signature, UploadError};
+
+    /// Starts of real files of each kind.
+    const PDF: &[u8] = b"%PDF-1.7\n";
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+    const ZIP: &[u8] = b"PK\x03\x04\x14\0\x06\0";
+    const OLE: &[u8] = b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1\0\0";
+
+    #[test]
+    fn sniffs_signatures() {
+        assert!(sniff(PDF) == Some(Signature::Pdf));
+        assert!(sniff(PNG) == Some(Signature::Png));
+        assert!(sniff(b"\xff\xd8\xff\xe0") == Some(Signature::Jpeg));
+        assert!(sniff(ZIP) == Some(Signature::Zip));
+        assert!(sniff(OLE) == Some(Signature::Ole));
+        assert!(sniff(b"%PD").is_none());
+        assert!(sniff(b"MZ\x90\0").is_none());
+    }
+
+    #[test]
+    fn rejects_content_not_matching_extension() {
+        let pdf = upload_format("report.PDF").ok().unwrap();
+        assert!(pdf.check_signature(PDF).is_ok());
+        assert!(matches!(pdf.check_signature(ZIP), Err(UploadError::SignatureMismatch("pdf"))));
+        assert!(matches!(upload_format("setup.exe"), Err(UploadError::UnsupportedExtension(extension)) if extension == "exe"));
+    }
+
+    #[test]
+    fn tells_office_formats_apart_by_marker() {
+        let docx = [ZIP, b"...word/document.xml...".as_slice()].concat();
+        let pptx = [ZIP, b"...ppt/presentation.xml...".as_slice()].concat();
+
+        let format = upload_format("letter.docx").ok().unwrap();
+        assert!(format.check_content(&docx).is_ok());
+        assert!(matches!(format.check_content(&pptx), Err(UploadError::SignatureMismatch("docx"))));
+        assert!(matches!(format.check_content(ZIP), Err(UploadError::SignatureMismatch("docx"))));
+
+        let doc = [OLE, b"W\0o\0r\0d\0D\0o\0c\0u\0m\0e\0n\0t\0".as_slice()].concat();
+        assert!(upload_format("letter.doc").ok().unwrap().check_content(&doc).is_ok());
+        assert!(upload_format("slides.ppt").ok().unwrap().check_content(&doc).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_uploads() {
+        let png = upload_format("image.png").ok().unwrap();
+        assert!(png.check_size(png.max_size).is_ok());
+        assert!(matches!(png.check_size(png.max_size + 1), Err(UploadError::TooLarge("png", _))));
+    }
+
+    #[test]
+    fn reads_extensions() {
+        assert_eq!(extension("archive.tar.GZ"), "gz");
+        assert_eq!(extension("README"), "");
+        assert_eq!(extension("trailing."), "");
+    }
+
+    #[test]
+    fn accepts_only_known_targets() {
+        assert!(is_target_format("pdf"));
+        assert!(!is_target_format("PDF"));
+        assert!(!is_target_format("pdf\" } }, \"evil\": {"));
+    }
+}
@@ -1,3 +1,17 @@
+use std::{fmt, time::Instant};
+
+use chrono::{DateTime, Utc};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, QueryResult, SelectableHelper};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+
+use tracing::{error, info};
+
+use crate::{
+    converter::backend,
+    database::{models::{Job, NewJob}, schema::jobs},
+    protocol::{Event, JobStage, ServerMessage},
+    State
+};
 
 #[derive(Eq, Hash, PartialEq, Clone)]
 pub struct JobId(pub String);
@@ -9,9 +23,565 @@ impl From<String> for JobId {
 
 }
 
-impl Into<String> for JobId {
+impl From<JobId> for String {
+
+    fn from(value: JobId) -> Self {
+        value.0
+    }
+}
+
+impl From<i32> for JobId {
+
+    fn from(value: i32) -> Self {
+        Self(value.to_string())
+    }
+
+}
+
+impl JobId {
+
+    /// The id of the job's row in `jobs`. Cache hits never get one.
+    pub fn row_id(&self) -> Option<i32> {
+        self.0.parse().ok()
+    }
+
+    /// The tag the job is submitted to CloudConvert with. Every attempt gets
+    /// its own, so an attempt can find the job it created at CloudConvert.
+    pub fn tag(&self, attempt: i32) -> String {
+        format!("{}-{}", self.0, attempt)
+    }
+
+    /// The job a CloudConvert tag was made for by [`JobId::tag`].
+    pub fn from_tag(tag: &str) -> JobId {
+        let id = tag.split_once('-').map_or(tag, |(id, _)| id);
+        JobId(id.to_string())
+    }
+
+    /// The attempt a CloudConvert tag was made for, if it names one.
+    pub fn attempt_from_tag(tag: &str) -> Option<i32> {
+        tag.split_once('-').and_then(|(_, attempt)| attempt.parse().ok())
+    }
+
+}
+
+/// Where a job is, as stored in `jobs.status`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobStatus {
+    /// Waiting in the submission queue.
+    Queued,
+    /// Taken from the queue and handed to the converter, waiting for its
+    /// webhook. Jobs still being submitted have no `backend_job_id` yet.
+    Processing,
+    Finished,
+    Failed,
+    /// Stopped by the user or an operator before it ended.
+    Cancelled
+}
+
+impl JobStatus {
+
+    pub const ALL: [JobStatus; 5] = [
+        JobStatus::Queued,
+        JobStatus::Processing,
+        JobStatus::Finished,
+        JobStatus::Failed,
+        JobStatus::Cancelled
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Processing => "processing",
+            JobStatus::Finished => "finished",
+            JobStatus::Failed => "failed",
+            JobStatus::Cancelled => "cancelled"
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<JobStatus> {
+        match status {
+            "queued" => Some(JobStatus::Queued),
+            "processing" => Some(JobStatus::Processing),
+            "finished" => Some(JobStatus::Finished),
+            "failed" => Some(JobStatus::Failed),
+            "cancelled" => Some(JobStatus::Cancelled),
+            _ => None
+        }
+    }
+
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, JobStatus::Finished | JobStatus::Failed | JobStatus::Cancelled)
+    }
+
+}
+
+impl fmt::Display for JobStatus {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+
+}
+
+/// Everything we know about a submitted job until its webhook arrives.
+#[derive(Clone)]
+pub struct PendingJob {
+    pub session_id: String,
+    pub source_file_name: String,
+    pub target_format: String,
+    pub cache_key: String,
+    /// Text extracted from the upload, stored when the result has none.
+    pub source_text: Option<String>,
+    /// Every message sent about this job so far, replayed on reconnect.
+    pub events: Vec<Event>,
+    /// When CloudConvert accepted the job, unknown for jobs restored after
+    /// a restart.
+    pub submitted_at: Option<Instant>
+}
+
+impl PendingJob {
+
+    /// The events a client that last saw `after` has missed. Clients that
+    /// saw nothing only need the latest status.
+    pub fn events_after(&self, after: Option<u64>) -> Vec<Event> {
+        match after {
+            Some(after) => self.events.iter()
+                .filter(|event| event.id > after)
+                .cloned()
+                .collect(),
+            None => self.events.last().cloned().into_iter().collect()
+        }
+    }
+
+    /// The queue position the job last reported.
+    pub fn position(&self) -> Option<usize> {
+        self.events.iter().rev().find_map(|event| match event.message {
+            ServerMessage::JobWaiting { position, .. } => Some(position),
+            _ => None
+        })
+    }
+
+    /// The stage the job last reported.
+    pub fn stage(&self) -> Option<JobStage> {
+        self.events.iter().rev().find_map(|event| match event.message {
+            ServerMessage::JobProgress { stage, .. } => Some(stage),
+            _ => None
+        })
+    }
+
+}
+
+/// Records a new job for `session_id`, returning its id.
+pub async fn create(conn: &mut AsyncPgConnection, new_job: &NewJob<'_>) -> QueryResult<JobId> {
+    diesel::insert_into(jobs::table)
+        .values(new_job)
+        .returning(jobs::id)
+        .get_result::<i32>(conn)
+        .await
+        .map(JobId::from)
+}
+
+/// Finds a job, but only for the session that started it.
+pub async fn find(conn: &mut AsyncPgConnection, job_id: &JobId, session_id: &str) -> QueryResult<Option<Job>> {
+    let Some(id) = job_id.row_id() else {
+        return Ok(None)
+    };
+
+    jobs::table
+        .filter(jobs::id.eq(id))
+        .filter(jobs::session_id.eq(session_id))
+        .select(Job::as_select())
+        .first(conn)
+        .await
+        .optional()
+}
+
+/// Rebuilds a job that is no longer in memory, e.g. after a restart, so its
+/// result can still be stored. Text extracted from the upload is lost.
+pub async fn restore(conn: &mut AsyncPgConnection, job_id: &JobId) -> QueryResult<Option<PendingJob>> {
+    let Some(id) = job_id.row_id() else {
+        return Ok(None)
+    };
+
+    let job = jobs::table
+        .find(id)
+        .select((jobs::session_id, jobs::source_file_name, jobs::target_format, jobs::cache_key))
+        .first::<(String, String, String, Option<String>)>(conn)
+        .await
+        .optional()?;
+
+    Ok(job.map(|(session_id, source_file_name, target_format, cache_key)| PendingJob {
+        session_id,
+        source_file_name,
+        target_format,
+        cache_key: cache_key.unwrap_or_default(),
+        source_text: None,
+        events: Vec::new(),
+        submitted_at: None
+    }))
+}
+
+/// The session's most recent jobs, newest first.
+pub async fn history(conn: &mut AsyncPgConnection, session_id: &str, limit: i64) -> QueryResult<Vec<Job>> {
+    jobs::table
+        .filter(jobs::session_id.eq(session_id))
+        .order(jobs::created_at.desc())
+        .limit(limit)
+        .select(Job::as_select())
+        .load(conn)
+        .await
+}
+
+/// The most recent jobs of every session, newest first, optionally only
+/// those with `status`.
+pub async fn recent(conn: &mut AsyncPgConnection, status: Option<JobStatus>, limit: i64) -> QueryResult<Vec<Job>> {
+    let mut query = jobs::table
+        .order(jobs::created_at.desc())
+        .limit(limit)
+        .select(Job::as_select())
+        .into_boxed();
+
+    if let Some(status) = status {
+        query = query.filter(jobs::status.eq(status.as_str()));
+    }
+
+    query.load(conn).await
+}
+
+/// Hands every job of the session `from` to the session `to`.
+pub async fn move_session(conn: &mut AsyncPgConnection, from: &str, to: &str) -> QueryResult<usize> {
+    diesel::update(jobs::table)
+        .filter(jobs::session_id.eq(from))
+        .set(jobs::session_id.eq(to))
+        .execute(conn)
+        .await
+}
+
+/// Finished jobs of the session it was never told about, marking them as
+/// told.
+pub async fn take_undelivered(conn: &mut AsyncPgConnection, session_id: &str) -> QueryResult<Vec<Job>> {
+    diesel::update(jobs::table)
+        .filter(jobs::session_id.eq(session_id))
+        .filter(jobs::notified_at.is_null())
+        .filter(jobs::status.eq_any([JobStatus::Finished.as_str(), JobStatus::Failed.as_str(), JobStatus::Cancelled.as_str()]))
+        .set(jobs::notified_at.eq(Utc::now()))
+        .returning(Job::as_returning())
+        .get_results(conn)
+        .await
+}
+
+/// Records that the session was told how the job ended.
+pub async fn mark_notified(conn: &mut AsyncPgConnection, job_id: &JobId) -> QueryResult<()> {
+    let Some(id) = job_id.row_id() else {
+        return Ok(())
+    };
+
+    diesel::update(jobs::table.find(id))
+        .set(jobs::notified_at.eq(Utc::now()))
+        .execute(conn)
+        .await
+        .map(|_| ())
+}
+
+/// The message that tells a client how the job ended, if it has.
+pub fn final_message(job: &Job) -> Option<ServerMessage> {
+    let job_id = job.id.to_string();
+    match (job.status(), job.file_id) {
+        (JobStatus::Finished, Some(file_id)) => Some(ServerMessage::JobCompleted { job_id, file_id }),
+        (JobStatus::Finished, None) => Some(ServerMessage::JobFailed {
+            job_id,
+            reason: "Your converted file is no longer available!".to_string()
+        }),
+        (JobStatus::Failed, _) => Some(ServerMessage::JobFailed {
+            job_id,
+            reason: job.error.clone().unwrap_or_else(|| "Your file could not be converted!".to_string())
+        }),
+        (JobStatus::Cancelled, _) => Some(ServerMessage::JobCancelled { job_id }),
+        _ => None
+    }
+}
+
+/// Records the id the converter gave a claimed job, returning false when
+/// the job was cancelled while it was being submitted or the id was already
+/// recorded, e.g. by a webhook that came first.
+pub async fn submitted(conn: &mut AsyncPgConnection, job_id: &JobId, backend_job_id: &str) -> QueryResult<bool> {
+    let Some(id) = job_id.row_id() else {
+        return Ok(false)
+    };
+
+    diesel::update(jobs::table.find(id))
+        .filter(jobs::status.eq(JobStatus::Processing.as_str()))
+        .filter(jobs::backend_job_id.is_null())
+        .set((
+            jobs::backend_job_id.eq(backend_job_id),
+            jobs::updated_at.eq(Utc::now())
+        ))
+        .execute(conn)
+        .await
+        .map(|updated| updated > 0)
+}
+
+/// Marks the job as done. Its upload is no longer needed once it is.
+pub async fn finish(conn: &mut AsyncPgConnection, job_id: &JobId, file_id: i32) -> QueryResult<()> {
+    update(conn, job_id, (
+        jobs::status.eq(JobStatus::Finished.as_str()),
+        jobs::file_id.eq(file_id),
+        jobs::error.eq(None::<String>),
+        jobs::input.eq(None::<String>)
+    )).await
+}
+
+pub async fn fail(conn: &mut AsyncPgConnection, job_id: &JobId, reason: &str) -> QueryResult<()> {
+    update(conn, job_id, (
+        jobs::status.eq(JobStatus::Failed.as_str()),
+        jobs::error.eq(reason)
+    )).await
+}
+
+async fn update<V>(conn: &mut AsyncPgConnection, job_id: &JobId, values: V) -> QueryResult<()>
+where
+    V: diesel::AsChangeset<Target = jobs::table>,
+    <V as diesel::AsChangeset>::Changeset: diesel::query_builder::QueryFragment<diesel::pg::Pg> + Send,
+    V: Send
+{
+    let Some(id) = job_id.row_id() else {
+        return Ok(())
+    };
+
+    diesel::update(jobs::table.find(id))
+        .set((values, jobs::updated_at.eq(Utc::now())))
+        .execute(conn)
+        .await
+        .map(|_| ())
+}
+
+/// A failed job put back in the queue by [`reset`].
+pub struct RetriedJob {
+    pub source_file_name: String,
+    pub target_format: String,
+    pub cache_key: Option<String>,
+    /// The upload, base64 encoded.
+    pub input: String,
+    pub attempts: i32
+}
+
+/// Queues a failed job of the session again if its upload is still stored,
+/// so it can be submitted once more.
+pub async fn reset(conn: &mut AsyncPgConnection, job_id: &JobId, session_id: &str) -> QueryResult<Option<RetriedJob>> {
+    let Some(id) = job_id.row_id() else {
+        return Ok(None)
+    };
+
+    let job = diesel::update(jobs::table.find(id))
+        .filter(jobs::session_id.eq(session_id))
+        .filter(jobs::status.eq(JobStatus::Failed.as_str()))
+        .filter(jobs::input.is_not_null())
+        .set((
+            jobs::status.eq(JobStatus::Queued.as_str()),
+            jobs::backend_job_id.eq(None::<String>),
+            jobs::error.eq(None::<String>),
+            jobs::notified_at.eq(None::<DateTime<Utc>>),
+            jobs::attempts.eq(jobs::attempts + 1),
+            jobs::updated_at.eq(Utc::now())
+        ))
+        .returning((jobs::source_file_name, jobs::target_format, jobs::cache_key, jobs::input, jobs::attempts))
+        .get_result::<(String, String, Option<String>, Option<String>, i32)>(conn)
+        .await
+        .optional()?;
+
+    Ok(job.and_then(|(source_file_name, target_format, cache_key, input, attempts)| Some(RetriedJob {
+        source_file_name,
+        target_format,
+        cache_key,
+        input: input?,
+        attempts
+    })))
+}
+
+/// Records what CloudConvert charged for the job, returning false when a
+/// charge was already recorded, e.g. by an earlier attempt at its webhook.
+pub async fn charged(conn: &mut AsyncPgConnection, job_id: &JobId, credits: i32) -> QueryResult<bool> {
+    let Some(id) = job_id.row_id() else {
+        return Ok(false)
+    };
+
+    diesel::update(jobs::table.find(id))
+        .filter(jobs::credits.is_null())
+        .set((
+            jobs::credits.eq(credits),
+            jobs::updated_at.eq(Utc::now())
+        ))
+        .execute(conn)
+        .await
+        .map(|updated| updated > 0)
+}
+
+/// Credits charged for jobs that ended since `since`.
+pub async fn credits_used_since(conn: &mut AsyncPgConnection, since: DateTime<Utc>) -> QueryResult<i64> {
+    jobs::table
+        .filter(jobs::credits.is_not_null())
+        .filter(jobs::updated_at.ge(since))
+        .select(diesel::dsl::sum(jobs::credits))
+        .first::<Option<i64>>(conn)
+        .await
+        .map(Option::unwrap_or_default)
+}
+
+/// The most recent jobs CloudConvert charged for, newest first.
+pub async fn recently_charged(conn: &mut AsyncPgConnection, limit: i64) -> QueryResult<Vec<Job>> {
+    jobs::table
+        .filter(jobs::credits.is_not_null())
+        .order(jobs::updated_at.desc())
+        .limit(limit)
+        .select(Job::as_select())
+        .load(conn)
+        .await
+}
+
+/// How far a submitted job got, as read by [`submission`].
+pub struct Submission {
+    pub status: JobStatus,
+    /// The id the converter gave the job, once it was submitted.
+    pub backend_job_id: Option<String>,
+    pub file_id: Option<i32>,
+    /// The attempt the job is on, as used in its tag.
+    pub attempts: i32
+}
+
+/// The stored status of a job, the id the converter gave it and the file it
+/// produced, if any.
+pub async fn submission(conn: &mut AsyncPgConnection, job_id: &JobId) -> QueryResult<Option<Submission>> {
+    let Some(id) = job_id.row_id() else {
+        return Ok(None)
+    };
+
+    let job = jobs::table
+        .find(id)
+        .select((jobs::status, jobs::backend_job_id, jobs::file_id, jobs::attempts))
+        .first::<(String, Option<String>, Option<i32>, i32)>(conn)
+        .await
+        .optional()?;
+
+    Ok(job.and_then(|(status, backend_job_id, file_id, attempts)| Some(Submission {
+        status: JobStatus::parse(&status)?,
+        backend_job_id,
+        file_id,
+        attempts
+    })))
+}
+
+pub enum CancelError {
+    NotFound,
+    /// The job ended before it could be cancelled.
+    AlreadyEnded,
+    /// The converter would not stop the job.
+    Backend
+}
+
+impl CancelError {
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            CancelError::NotFound => "We couldn't find that conversion!",
+            CancelError::AlreadyEnded => "This conversion has already ended!",
+            CancelError::Backend => "The conversion could not be stopped, please try again!"
+        }
+    }
+
+}
+
+/// Stops a job that hasn't ended yet and tells its session. `session_id`
+/// limits this to the session's own jobs, operators pass `None`.
+pub(crate) async fn cancel(
+    state: &State,
+    conn: &mut AsyncPgConnection,
+    job_id: &JobId,
+    session_id: Option<&str>
+) -> Result<(), CancelError> {
+    let id = job_id.row_id().ok_or(CancelError::NotFound)?;
+
+    let mut query = jobs::table
+        .filter(jobs::id.eq(id))
+        .select((jobs::status, jobs::backend_job_id))
+        .into_boxed();
+
+    if let Some(session_id) = session_id {
+        query = query.filter(jobs::session_id.eq(session_id));
+    }
+
+    let job = query.first::<(String, Option<String>)>(conn)
+        .await
+        .optional()
+        .map_err(|err| {
+            error!("[{}] Unable to look up job to cancel: {}", job_id.0, err);
+            CancelError::NotFound
+        })?;
+
+    let (status, backend_job_id) = job.ok_or(CancelError::NotFound)?;
+    if JobStatus::parse(&status).is_none_or(|status| status.is_terminal()) {
+        return Err(CancelError::AlreadyEnded)
+    }
+
+    if let Some(backend_job_id) = backend_job_id {
+        if let Err(err) = backend::cancel_job(&backend_job_id).await {
+            error!("[{}] Unable to cancel job at CloudConvert: {}", job_id.0, err);
+            return Err(CancelError::Backend)
+        }
+    }
+
+    // Only running jobs are cancelled, a result that arrived meanwhile wins.
+    let cancelled = diesel::update(jobs::table.find(id))
+        .filter(jobs::status.eq_any([JobStatus::Queued.as_str(), JobStatus::Processing.as_str()]))
+        .set((
+            jobs::status.eq(JobStatus::Cancelled.as_str()),
+            jobs::input.eq(None::<String>),
+            jobs::updated_at.eq(Utc::now())
+        ))
+        .execute(conn)
+        .await
+        .map_err(|err| {
+            error!("[{}] Unable to record cancellation: {}", job_id.0, err);
+            CancelError::Backend
+        })?;
+
+    if cancelled == 0 {
+        return Err(CancelError::AlreadyEnded)
+    }
+
+    info!("[{}] Job was cancelled!", job_id.0);
+    state.submissions.wake();
+    let message = ServerMessage::JobCancelled { job_id: job_id.0.clone() };
+    if state.publish(job_id, message).await {
+        if let Err(err) = mark_notified(conn, job_id).await {
+            error!("[{}] Unable to record notification: {}", job_id.0, err);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JobId;
+
+    #[test]
+    fn tags_name_job_and_attempt() {
+        let job_id = JobId::from(42);
+        assert_eq!(job_id.tag(1), "42-1");
+        assert_eq!(job_id.tag(3), "42-3");
+    }
+
+    #[test]
+    fn tags_lead_back_to_the_job() {
+        assert_eq!(JobId::from_tag("42-3").0, "42");
+        // Jobs submitted before tags carried the attempt.
+        assert_eq!(JobId::from_tag("42").0, "42");
+    }
 
-    fn into(self) -> String {
-        self.0
+    #[test]
+    fn tags_name_their_attempt() {
+        assert_eq!(JobId::attempt_from_tag("42-3"), Some(3));
+        assert_eq!(JobId::attempt_from_tag("42"), None);
+        assert_eq!(JobId::attempt_from_tag("42-x"), None);
     }
 }
@@ -0,0 +1,412 @@
+use std::{env, time::Duration};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{TimeDelta, Utc};
+use diesel::{
+    dsl::sql, pg::Pg, sql_types::BigInt, BoolExpressionMethods, ExpressionMethods, OptionalExtension, QueryDsl,
+    QueryResult
+};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use tokio::sync::Notify;
+use tracing::{error, info};
+
+use crate::{
+    converter::{formats, jobs::{JobId, JobStatus, PendingJob}, submit, text},
+    database::schema::jobs,
+    protocol::ServerMessage,
+    SharedState, State
+};
+
+/// How many jobs may be at CloudConvert at once by default.
+const DEFAULT_MAX_IN_FLIGHT: i64 = 10;
+
+/// How often the queue is looked at when nothing wakes it, e.g. for jobs
+/// whose webhook never arrived.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Jobs processing for longer than this no longer count as in flight, so a
+/// lost webhook can't hold a slot forever.
+const STALE_AFTER_MINUTES: i64 = 60;
+
+/// Shown for jobs queued before uploads were kept, which can't be submitted.
+const INPUT_LOST: &str = "Your upload was lost, please upload your file again!";
+
+/// Lanes of the queue, stored as `jobs.priority`. Higher lanes are always
+/// submitted first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Priority {
+    Standard,
+    /// Jobs started by a logged in operator.
+    Staff
+}
+
+impl Priority {
+
+    pub fn value(&self) -> i16 {
+        match self {
+            Priority::Standard => 0,
+            Priority::Staff => 10
+        }
+    }
+
+}
+
+/// Jobs waiting to be handed to CloudConvert. The queue itself lives in
+/// `jobs`, so it survives restarts; this only wakes the dispatcher.
+pub struct SubmissionQueue {
+    wake: Notify,
+    max_in_flight: i64
+}
+
+impl SubmissionQueue {
+
+    /// Limited by `MAX_IN_FLIGHT_JOBS`.
+    pub fn from_env() -> Self {
+        let max_in_flight = env::var("MAX_IN_FLIGHT_JOBS")
+            .ok()
+            .and_then(|max| max.parse().ok())
+            .filter(|max| *max > 0)
+            .unwrap_or(DEFAULT_MAX_IN_FLIGHT);
+
+        SubmissionQueue {
+            wake: Notify::new(),
+            max_in_flight
+        }
+    }
+
+    /// Asks the dispatcher to look at the queue, after a job was queued or
+    /// a slot freed up.
+    pub fn wake(&self) {
+        self.wake.notify_one();
+    }
+
+}
+
+/// Submits queued jobs whenever fewer than `MAX_IN_FLIGHT_JOBS` are at
+/// CloudConvert.
+pub async fn start(state: SharedState) {
+    recover(&state).await;
+
+    info!("Submitting at most {} jobs at once", state.submissions.max_in_flight);
+    tokio::spawn(async move {
+        loop {
+            dispatch(&state).await;
+            tokio::select! {
+                _ = state.submissions.wake.notified() => {},
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            }
+        }
+    });
+}
+
+/// Queues jobs again that were being submitted when the server stopped, and
+/// fails queued jobs that have no upload to submit.
+async fn recover(state: &State) {
+    let mut conn = match state.pool.get().await {
+        Ok(conn) => conn,
+        Err(err) => {
+            error!("Unable to connect to database to recover the queue: {}", err);
+            return
+        }
+    };
+
+    let requeued = diesel::update(jobs::table)
+        .filter(jobs::status.eq(JobStatus::Processing.as_str()))
+        .filter(jobs::backend_job_id.is_null())
+        .set(jobs::status.eq(JobStatus::Queued.as_str()))
+        .execute(&mut conn)
+        .await;
+
+    match requeued {
+        Ok(0) => {},
+        Ok(requeued) => info!("Queued {} interrupted submissions again", requeued),
+        Err(err) => error!("Unable to queue interrupted submissions again: {}", err)
+    }
+
+    let lost = diesel::update(jobs::table)
+        .filter(jobs::status.eq(JobStatus::Queued.as_str()))
+        .filter(jobs::input.is_null())
+        .set((
+            jobs::status.eq(JobStatus::Failed.as_str()),
+            jobs::error.eq(INPUT_LOST),
+            jobs::updated_at.eq(Utc::now())
+        ))
+        .execute(&mut conn)
+        .await;
+
+    if let Err(err) = lost {
+        error!("Unable to fail queued jobs without upload: {}", err);
+    }
+}
+
+/// Claims queued jobs until the in-flight limit is reached, then tells every
+/// waiting job where it is.
+async fn dispatch(state: &SharedState) {
+    let mut conn = match state.pool.get().await {
+        Ok(conn) => conn,
+        Err(err) => {
+            error!("Unable to connect to database to dispatch jobs: {}", err);
+            return
+        }
+    };
+
+    loop {
+        // Held until the credit monitor sees the balance topped up.
+        if state.credits.is_low().await {
+            break
+        }
+
+        match in_flight(&mut conn).await {
+            Ok(in_flight) if in_flight >= state.submissions.max_in_flight => break,
+            Ok(_) => {},
+            Err(err) => {
+                error!("Unable to count jobs in flight: {}", err);
+                return
+            }
+        }
+
+        match claim_next(&mut conn).await {
+            Ok(Some(job)) => {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    submit_claimed(&state, job).await;
+                });
+            },
+            Ok(None) => break,
+            Err(err) => {
+                error!("Unable to claim the next job: {}", err);
+                break
+            }
+        }
+    }
+
+    announce_positions(state, &mut conn).await;
+}
+
+/// Jobs at CloudConvert or being submitted there.
+async fn in_flight(conn: &mut AsyncPgConnection) -> QueryResult<i64> {
+    jobs::table
+        .filter(jobs::status.eq(JobStatus::Processing.as_str()))
+        .filter(jobs::updated_at.gt(Utc::now() - TimeDelta::minutes(STALE_AFTER_MINUTES)))
+        .count()
+        .get_result(conn)
+        .await
+}
+
+/// Queued jobs in the order they are submitted: by lane, then sessions with
+/// the fewest jobs in flight first, so one session's burst can't starve the
+/// others, then oldest first.
+fn queued() -> jobs::BoxedQuery<'static, Pg> {
+    let running = sql::<BigInt>(
+        "(SELECT count(*) FROM jobs AS running \
+          WHERE running.session_id = jobs.session_id AND running.status = 'processing')"
+    );
+
+    jobs::table
+        .filter(jobs::status.eq(JobStatus::Queued.as_str()).and(jobs::input.is_not_null()))
+        .order((jobs::priority.desc(), running.asc(), jobs::created_at.asc(), jobs::id.asc()))
+        .into_boxed()
+}
+
+/// A job taken from the queue, marked as processing.
+struct ClaimedJob {
+    job_id: JobId,
+    session_id: String,
+    source_file_name: String,
+    target_format: String,
+    cache_key: Option<String>,
+    /// The upload, base64 encoded.
+    input: Option<String>,
+    /// How often the job was queued, counting this time.
+    attempt: i32
+}
+
+async fn claim_next(conn: &mut AsyncPgConnection) -> QueryResult<Option<ClaimedJob>> {
+    loop {
+        let next = queued()
+            .select(jobs::id)
+            .first::<i32>(conn)
+            .await
+            .optional()?;
+
+        let Some(id) = next else {
+            return Ok(None)
+        };
+
+        // Cancelled jobs leave the queue between the two queries.
+        let claimed = diesel::update(jobs::table.find(id))
+            .filter(jobs::status.eq(JobStatus::Queued.as_str()))
+            .set((
+                jobs::status.eq(JobStatus::Processing.as_str()),
+                jobs::updated_at.eq(Utc::now())
+            ))
+            .returning((jobs::session_id, jobs::source_file_name, jobs::target_format, jobs::cache_key, jobs::input, jobs::attempts))
+            .get_result::<(String, String, String, Option<String>, Option<String>, i32)>(conn)
+            .await
+            .optional()?;
+
+        if let Some((session_id, source_file_name, target_format, cache_key, input, attempt)) = claimed {
+            return Ok(Some(ClaimedJob {
+                job_id: JobId::from(id),
+                session_id,
+                source_file_name,
+                target_format,
+                cache_key,
+                input,
+                attempt
+            }))
+        }
+    }
+}
+
+async fn submit_claimed(state: &SharedState, job: ClaimedJob) {
+    let job_id = job.job_id;
+    let mut conn = match state.pool.get().await {
+        Ok(conn) => conn,
+        Err(err) => {
+            // Queued again by `recover` on the next start.
+            error!("[{}] Unable to connect to database to submit job: {}", job_id.0, err);
+            return
+        }
+    };
+
+    let input_file_contents = match job.input.map(|input| STANDARD.decode(input)) {
+        Some(Ok(contents)) => contents,
+        _ => {
+            error!("[{}] Job has no readable upload!", job_id.0);
+            if let Err(err) = super::jobs::fail(&mut conn, &job_id, INPUT_LOST).await {
+                error!("[{}] Unable to record failure: {}", job_id.0, err);
+            }
+
+            state.publish(&job_id, ServerMessage::JobFailed {
+                job_id: job_id.0.clone(),
+                reason: INPUT_LOST.to_string()
+            }).await;
+
+            state.submissions.wake();
+            return
+        }
+    };
+
+    // Jobs queued before a restart are only known to the database.
+    if !state.pending_jobs.read().await.contains_key(&job_id) {
+        let source_text = text::extract(formats::extension(&job.source_file_name), input_file_contents.clone()).await;
+        state.pending_jobs.write().await.insert(job_id.clone(), PendingJob {
+            session_id: job.session_id,
+            source_file_name: job.source_file_name.clone(),
+            target_format: job.target_format.clone(),
+            cache_key: job.cache_key.unwrap_or_default(),
+            source_text,
+            events: Vec::new(),
+            submitted_at: None
+        });
+    }
+
+    info!("[{}] Submitting {} from the queue", job_id.0, job.source_file_name);
+    let submitted = submit::submit(
+        state,
+        &mut conn,
+        &job_id,
+        job.attempt,
+        &job.source_file_name,
+        &input_file_contents,
+        &job.target_format
+    ).await;
+    if submitted.is_err() {
+        state.submissions.wake();
+    }
+}
+
+/// Tells every waiting job whose position changed where it is now.
+async fn announce_positions(state: &State, conn: &mut AsyncPgConnection) {
+    let queued = match queued().select(jobs::id).load::<i32>(conn).await {
+        Ok(queued) => queued,
+        Err(err) => {
+            error!("Unable to look up queue positions: {}", err);
+            return
+        }
+    };
+
+    for (index, id) in queued.into_iter().enumerate() {
+        let job_id = JobId::from(id);
+        let position = index + 1;
+        let changed = state.pending_jobs.read().await
+            .get(&job_id)
+            .is_some_and(|job| job.position() != Some(position));
+
+        if changed {
+            state.publish(&job_id, ServerMessage::JobWaiting {
+                job_id: job_id.0.clone(),
+                position
+            }).await;
+        }
+    }
+}
+
+pub enum RetryError {
+    NotFound,
+    /// Only failed jobs whose upload we still have can be tried again.
+    NotRetryable,
+    Unavailable
+}
+
+impl RetryError {
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            RetryError::NotFound => "We couldn't find that conversion!",
+            RetryError::NotRetryable => "This conversion can't be tried again, please upload your file again!",
+            RetryError::Unavailable => "Something went wrong while trying to convert the requested file!"
+        }
+    }
+
+}
+
+/// Queues a failed job of the session once more, from the upload stored
+/// with it.
+pub(crate) async fn retry(
+    state: &State,
+    conn: &mut AsyncPgConnection,
+    job_id: &JobId,
+    session_id: &str
+) -> Result<(), RetryError> {
+    let job = match super::jobs::reset(conn, job_id, session_id).await {
+        Ok(Some(job)) => job,
+        Ok(None) => return match super::jobs::find(conn, job_id, session_id).await {
+            Ok(Some(_)) => Err(RetryError::NotRetryable),
+            Ok(None) => Err(RetryError::NotFound),
+            Err(err) => {
+                error!("[{}] Unable to look up job to retry: {}", job_id.0, err);
+                Err(RetryError::Unavailable)
+            }
+        },
+        Err(err) => {
+            error!("[{}] Unable to reset job for retry: {}", job_id.0, err);
+            return Err(RetryError::Unavailable)
+        }
+    };
+
+    info!("[{}] Retrying job, attempt {}", job_id.0, job.attempts);
+    let source_text = match STANDARD.decode(&job.input) {
+        Ok(contents) => text::extract(formats::extension(&job.source_file_name), contents).await,
+        Err(_) => None
+    };
+
+    state.pending_jobs.write().await.insert(job_id.clone(), PendingJob {
+        session_id: session_id.to_string(),
+        source_file_name: job.source_file_name.clone(),
+        target_format: job.target_format,
+        cache_key: job.cache_key.unwrap_or_default(),
+        source_text,
+        events: Vec::new(),
+        submitted_at: None
+    });
+
+    state.publish(job_id, ServerMessage::JobQueued {
+        job_id: job_id.0.clone(),
+        file_name: job.source_file_name
+    }).await;
+
+    state.submissions.wake();
+    Ok(())
+}
@@ -0,0 +1,189 @@
+use std::{env, time::{Duration, Instant}};
+
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use reqwest::{header::RETRY_AFTER, RequestBuilder, Response, StatusCode};
+use tracing::warn;
+
+use crate::metrics::Metrics;
+
+/// How often a request is tried before its failure is passed on.
+const DEFAULT_ATTEMPTS: u32 = 4;
+
+/// The wait before the first retry, doubled for every one after.
+const DEFAULT_BASE_DELAY_MS: u64 = 500;
+
+/// The longest we back off on our own between two attempts.
+const DEFAULT_MAX_DELAY_MS: u64 = 30_000;
+
+/// The longest `Retry-After` we are willing to honour. Asking us to wait
+/// longer ends the retries, the user shouldn't be left waiting that long.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(120);
+
+/// How requests to CloudConvert are retried when they fail transiently.
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration
+}
+
+impl RetryPolicy {
+
+    /// Read from `RETRY_ATTEMPTS`, `RETRY_BASE_DELAY_MS` and
+    /// `RETRY_MAX_DELAY_MS`.
+    pub fn from_env() -> Self {
+        let attempts = env::var("RETRY_ATTEMPTS")
+            .ok()
+            .and_then(|attempts| attempts.parse().ok())
+            .filter(|attempts| *attempts > 0)
+            .unwrap_or(DEFAULT_ATTEMPTS);
+
+        let base_delay = env::var("RETRY_BASE_DELAY_MS")
+            .ok()
+            .and_then(|delay| delay.parse().ok())
+            .unwrap_or(DEFAULT_BASE_DELAY_MS);
+
+        let max_delay = env::var("RETRY_MAX_DELAY_MS")
+            .ok()
+            .and_then(|delay| delay.parse().ok())
+            .unwrap_or(DEFAULT_MAX_DELAY_MS);
+
+        RetryPolicy {
+            attempts,
+            base_delay: Duration::from_millis(base_delay),
+            max_delay: Duration::from_millis(max_delay)
+        }
+    }
+
+    /// Exponential backoff with equal jitter: at least half of the capped
+    /// delay, so retries of many jobs don't line up.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let delay = self.base_delay
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_delay);
+
+        let half = delay / 2;
+        half + rand::thread_rng().gen_range(Duration::ZERO..=half)
+    }
+
+}
+
+/// Sends the request, retrying connection errors, timeouts, 429s and 5xx
+/// responses. The last response is returned as it is, so callers still see
+/// error statuses; only network errors end up as `Err`. Every attempt is
+/// timed as `operation`.
+pub async fn send(metrics: &Metrics, operation: &str, request: RequestBuilder, what: &str) -> reqwest::Result<Response> {
+    send_with(metrics, operation, request, what, false).await
+}
+
+/// Like [`send`], for requests that must not be repeated once CloudConvert
+/// may have acted on them, like creating a job. Only failed connections and
+/// 429s are retried, CloudConvert never saw or refused those.
+pub async fn send_once(metrics: &Metrics, operation: &str, request: RequestBuilder, what: &str) -> reqwest::Result<Response> {
+    send_with(metrics, operation, request, what, true).await
+}
+
+async fn send_with(metrics: &Metrics, operation: &str, request: RequestBuilder, what: &str, once: bool) -> reqwest::Result<Response> {
+    let policy = RetryPolicy::from_env();
+
+    let mut retry = 0;
+    loop {
+        // Bodies we send are always buffered, so the request can be cloned.
+        let attempt = request.try_clone().expect("Retried requests must not stream their body!");
+        let last = retry + 1 >= policy.attempts;
+
+        let started = Instant::now();
+        let sent = attempt.send().await;
+        metrics.backend_latency.observe(&[operation], started.elapsed().as_secs_f64());
+
+        let delay = match sent {
+            Ok(response) if last || !is_transient(response.status(), once) => return Ok(response),
+            Ok(response) => {
+                let status = response.status();
+                match retry_after(&response) {
+                    Some(delay) if delay > MAX_RETRY_AFTER => {
+                        warn!("{} was refused with {} for {}s, giving up!", what, status, delay.as_secs());
+                        return Ok(response)
+                    },
+                    Some(delay) => {
+                        warn!("{} was refused with {}, retrying in {}ms as asked", what, status, delay.as_millis());
+                        delay
+                    },
+                    None => {
+                        let delay = policy.backoff(retry);
+                        warn!("{} failed with {}, retrying in {}ms", what, status, delay.as_millis());
+                        delay
+                    }
+                }
+            },
+            Err(err) if !last && (err.is_connect() || (!once && (err.is_timeout() || err.is_request()))) => {
+                let delay = policy.backoff(retry);
+                warn!("{} failed: {}, retrying in {}ms", what, err, delay.as_millis());
+                delay
+            },
+            Err(err) => return Err(err)
+        };
+
+        tokio::time::sleep(delay).await;
+        retry += 1;
+    }
+}
+
+fn is_transient(status: StatusCode, once: bool) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || (!once && status.is_server_error())
+}
+
+/// `Retry-After` as either seconds or an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds))
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    let delay = date.with_timezone(&Utc) - Utc::now();
+    Some(delay.to_std().unwrap_or(Duration::ZERO))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use reqwest::{header::RETRY_AFTER, Response};
+
+    use super::{retry_after, RetryPolicy};
+
+    fn response(retry_after: &str) -> Response {
+        axum::http::Response::builder()
+            .status(429)
+            .header(RETRY_AFTER, retry_after)
+            .body("")
+            .unwrap()
+            .into()
+    }
+
+    #[test]
+    fn reads_retry_after_seconds_and_dates() {
+        assert_eq!(retry_after(&response("30")), Some(Duration::from_secs(30)));
+        assert_eq!(retry_after(&response("Wed, 21 Oct 2015 07:28:00 GMT")), Some(Duration::ZERO));
+        assert_eq!(retry_after(&response("soon")), None);
+
+        let later = (chrono::Utc::now() + chrono::Duration::seconds(90)).to_rfc2822();
+        let delay = retry_after(&response(&later)).unwrap();
+        assert!(delay > Duration::from_secs(80) && delay <= Duration::from_secs(90));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let policy = RetryPolicy {
+            attempts: 4,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000)
+        };
+
+        for (retry, full) in [(0, 100), (1, 200), (3, 800), (4, 1000), (40, 1000)] {
+            let delay = policy.backoff(retry);
+            assert!(delay >= Duration::from_millis(full / 2) && delay <= Duration::from_millis(full));
+        }
+    }
+}
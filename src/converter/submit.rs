@@ -0,0 +1,275 @@
+use std::{env, time::Instant};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use diesel_async::AsyncPgConnection;
+use hyper::StatusCode;
+use serde_json::{json, Value};
+use reqwest::Response;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    converter::{backend, jobs::{self, JobId}, retry::{self, RetryPolicy}},
+    metrics::Metrics,
+    protocol::{JobStage, ServerMessage},
+    response::CreateResponse,
+    State
+};
+
+/// Shown when CloudConvert kept failing or answered with something we
+/// don't understand.
+const SUBMIT_FAILED: &str = "Something went wrong while trying to convert the requested file!";
+
+/// Shown when CloudConvert couldn't be reached at all.
+const UNAVAILABLE: &str = "The converter is unavailable right now, please try again later!";
+
+/// Target formats previews are rendered for unless `PREVIEW_FORMATS` says
+/// otherwise.
+const DEFAULT_PREVIEW_FORMATS: &str = "pdf,docx,pptx";
+
+/// Everything passed to the convert task besides its input, so that any
+/// option added here also becomes part of the cache key.
+pub fn convert_options(target_format: &str) -> Value {
+    json!({
+        "output_format": target_format,
+    })
+}
+
+/// Whether CloudConvert renders a preview of results in `target_format`.
+/// Thumbnails cost credits, so only `PREVIEW_FORMATS` get one, by default
+/// the first page of PDFs and office documents.
+fn wants_preview(target_format: &str) -> bool {
+    let formats = env::var("PREVIEW_FORMATS").unwrap_or_else(|_| DEFAULT_PREVIEW_FORMATS.to_string());
+    is_listed(&formats, target_format)
+}
+
+/// Whether the comma separated `formats` include `target_format`.
+fn is_listed(formats: &str, target_format: &str) -> bool {
+    formats.split(',').any(|format| format.trim().eq_ignore_ascii_case(target_format))
+}
+
+/// Hands a job claimed from the queue to CloudConvert and tells its session
+/// how that went, returning the reason shown to the user if it failed.
+pub(crate) async fn submit(
+    state: &State,
+    conn: &mut AsyncPgConnection,
+    job_id: &JobId,
+    attempt: i32,
+    input_file_name: &str,
+    input_file_contents: &[u8],
+    target_format: &str
+) -> Result<(), &'static str> {
+    match start_job(&state.metrics, job_id, attempt, input_file_name, input_file_contents, target_format).await {
+        Ok(backend_job_id) => {
+            info!("[{}] Job was accepted as {}", job_id.0, backend_job_id);
+            if let Some(pending_job) = state.pending_jobs.write().await.get_mut(job_id) {
+                pending_job.submitted_at = Some(Instant::now());
+            }
+
+            match jobs::submitted(conn, job_id, &backend_job_id).await {
+                Ok(true) => {},
+                Ok(false) => {
+                    // Its webhook may have come first and recorded the id.
+                    let recorded = jobs::submission(conn, job_id).await
+                        .ok()
+                        .flatten()
+                        .and_then(|submission| submission.backend_job_id);
+
+                    if recorded.as_deref() == Some(backend_job_id.as_str()) {
+                        info!("[{}] Its webhook already recorded the submission!", job_id.0);
+                        return Ok(())
+                    }
+
+                    info!("[{}] Job was cancelled while it was submitted!", job_id.0);
+                    if let Err(err) = backend::cancel_job(&backend_job_id).await {
+                        error!("[{}] Unable to cancel job at CloudConvert: {}", job_id.0, err);
+                    }
+
+                    return Ok(())
+                },
+                Err(err) => error!("[{}] Unable to record submission: {}", job_id.0, err)
+            }
+
+            state.publish(job_id, ServerMessage::JobProgress {
+                job_id: job_id.0.clone(),
+                stage: JobStage::Converting
+            }).await;
+
+            Ok(())
+        },
+        Err(reason) => {
+            state.metrics.failures.increment(&["submit", reason]);
+            if let Err(err) = jobs::fail(conn, job_id, reason).await {
+                error!("[{}] Unable to record failure: {}", job_id.0, err);
+            }
+
+            let message = ServerMessage::JobFailed {
+                job_id: job_id.0.clone(),
+                reason: reason.to_string()
+            };
+
+            if state.publish(job_id, message).await {
+                if let Err(err) = jobs::mark_notified(conn, job_id).await {
+                    error!("[{}] Unable to record notification: {}", job_id.0, err);
+                }
+            }
+
+            Err(reason)
+        }
+    }
+}
+
+/// Creates the job at CloudConvert, returning the id it was given there.
+async fn start_job(
+    metrics: &Metrics,
+    job_id: &JobId,
+    attempt: i32,
+    input_file_name: &str,
+    input_file_contents: &[u8],
+    target_format: &str
+) -> Result<String, &'static str> {
+    let mut convert_task = convert_options(target_format);
+    convert_task["operation"] = json!("convert");
+    convert_task["input"] = json!("import-my-file");
+
+    let mut tasks = json!({
+        "import-my-file": {
+            "operation": "import/base64",
+            "file": STANDARD.encode(input_file_contents),
+            "filename": input_file_name,
+        },
+
+        "convert-my-file": convert_task,
+
+        "export-my-file": {
+            "operation": "export/url",
+            "input": "convert-my-file"
+        }
+    });
+
+    if wants_preview(target_format) {
+        // Previews are best effort and must never fail the conversion.
+        tasks["thumbnail-my-file"] = json!({
+            "operation": "thumbnail",
+            "input": "convert-my-file",
+            "output_format": "png",
+            "width": 400,
+            "height": 400,
+            "fit": "max",
+            "ignore_error": true
+        });
+
+        tasks["export-my-thumbnail"] = json!({
+            "operation": "export/url",
+            "input": "thumbnail-my-file",
+            "ignore_error": true
+        });
+    }
+
+    let tag = job_id.tag(attempt);
+    let client = reqwest::Client::new();
+    let request = client.post(format!("{}/v2/jobs", backend::base_url()))
+        .bearer_auth(backend::api_key())
+        .json(&json!({
+            "tasks": tasks,
+            // Comes back in the webhook, which is how we find our job again.
+            "tag": &tag,
+            "redirect": true
+        }));
+
+    let what = format!("[{}] Job submission", job_id.0);
+    let policy = RetryPolicy::from_env();
+    let mut retry = 0;
+    loop {
+        info!("[{}] Starting POST request to CloudConvert...", job_id.0);
+        let attempt = request.try_clone().expect("Job submissions must not stream their body!");
+        let job_response = retry::send_once(metrics, "submit", attempt, &what).await;
+
+        // Timeouts and server errors may come after CloudConvert created the
+        // job, creating it again would run and charge it twice.
+        let maybe_created = match &job_response {
+            Ok(response) => response.status().is_server_error(),
+            Err(err) => !err.is_connect()
+        };
+
+        if !maybe_created {
+            return read_created(job_id, job_response).await
+        }
+
+        let delay = policy.backoff(retry);
+        warn!("{} failed, looking for the job in {}ms", what, delay.as_millis());
+        tokio::time::sleep(delay).await;
+
+        match backend::find_job(metrics, &tag).await {
+            Ok(Some(backend_job_id)) => {
+                info!("[{}] Job was created at CloudConvert all the same!", job_id.0);
+                return Ok(backend_job_id)
+            },
+            Ok(None) if retry + 1 < policy.attempts => retry += 1,
+            Ok(None) => return read_created(job_id, job_response).await,
+            Err(err) => {
+                error!("[{}] Unable to look for the job at CloudConvert: {}", job_id.0, err);
+                return Err(UNAVAILABLE)
+            }
+        }
+    }
+}
+
+/// The id of the job CloudConvert created, or the reason shown to the user.
+async fn read_created(job_id: &JobId, job_response: reqwest::Result<Response>) -> Result<String, &'static str> {
+    let job_response = match job_response {
+        Ok(job_response) => job_response,
+        Err(err) => {
+            error!("[{}] Unable to reach CloudConvert: {}", job_id.0, err);
+            return Err(UNAVAILABLE)
+        }
+    };
+
+    info!("[{}] Recieved response from CloudConvert!", job_id.0);
+    debug!("[{}] Response: {:?}", job_id.0, job_response);
+    match job_response.status() {
+        StatusCode::CREATED => {
+            let body = match job_response.json::<Value>().await {
+                Ok(body) => body,
+                Err(err) => {
+                    debug!("[{}] Error while reading response: {}", job_id.0, err);
+                    return Err(SUBMIT_FAILED)
+                }
+            };
+
+            match serde_json::from_value::<CreateResponse>(body["data"].clone()) {
+                Ok(response) => Ok(response.id),
+                Err(_) => {
+                    debug!("[{}] No 'data' field in response!", job_id.0);
+                    Err(SUBMIT_FAILED)
+                }
+            }
+        },
+        code if code == StatusCode::TOO_MANY_REQUESTS || code.is_server_error() => {
+            info!("[{}] CloudConvert is still unavailable: {}", job_id.0, code);
+            Err(UNAVAILABLE)
+        },
+        code => {
+            info!("[{}] Recieved the wrong status code from CloudConvert: {}", job_id.0, code);
+            Err(SUBMIT_FAILED)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_listed, DEFAULT_PREVIEW_FORMATS};
+
+    #[test]
+    fn previews_pdfs_and_office_documents_by_default() {
+        for format in ["pdf", "docx", "pptx"] {
+            assert!(is_listed(DEFAULT_PREVIEW_FORMATS, format));
+        }
+    }
+
+    #[test]
+    fn reads_preview_format_lists() {
+        assert!(is_listed("pdf, DOCX", "docx"));
+        assert!(!is_listed("pdf,docx", "pptx"));
+        assert!(!is_listed("", "pdf"));
+    }
+}
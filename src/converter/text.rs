@@ -0,0 +1,214 @@
+use std::{
+    borrow::Cow,
+    io::{Cursor, Read},
+    panic::{self, AssertUnwindSafe},
+    time::Duration
+};
+
+use quick_xml::{events::Event, Reader};
+use tokio::sync::Semaphore;
+use tracing::{debug, warn};
+use zip::ZipArchive;
+
+/// Upper bound on stored text, comfortably below Postgres' 1 MB tsvector limit.
+const MAX_TEXT_LENGTH: usize = 256 * 1024;
+
+/// Upper bound on the XML decompressed from an office document. Markup
+/// outweighs the text in it many times over, but a zip bomb is cut short.
+const MAX_XML_SIZE: usize = MAX_TEXT_LENGTH * 32;
+
+/// How long extraction may take before the text is given up on.
+const EXTRACT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Extractions running at once. A document that outlives its timeout keeps
+/// its permit until it is done, so hostile ones can't pile up.
+static EXTRACTIONS: Semaphore = Semaphore::const_new(4);
+
+/// Runs [`extract_text`] on the blocking thread pool, giving up after
+/// `EXTRACT_TIMEOUT`.
+pub async fn extract(extension: String, bytes: Vec<u8>) -> Option<String> {
+    let extraction = async {
+        let permit = EXTRACTIONS.acquire().await.ok()?;
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            extract_text(&extension, &bytes)
+        }).await.ok().flatten()
+    };
+
+    match tokio::time::timeout(EXTRACT_TIMEOUT, extraction).await {
+        Ok(text) => text,
+        Err(_) => {
+            warn!("Text extraction took longer than {}s, skipping it!", EXTRACT_TIMEOUT.as_secs());
+            None
+        }
+    }
+}
+
+/// Extracts the plain text of a PDF, DOCX or PPTX document, or `None` when
+/// the format isn't supported or the document holds no text.
+///
+/// Parsing is CPU bound, prefer [`extract`] from async code.
+pub fn extract_text(extension: &str, bytes: &[u8]) -> Option<String> {
+    let text = match extension {
+        "pdf" => extract_pdf(bytes),
+        "docx" => extract_office(bytes, |name| name == "word/document.xml", b"w:t", b"w:p"),
+        "pptx" => extract_office(bytes, is_slide, b"a:t", b"a:p"),
+        _ => None
+    }?;
+
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.is_empty() {
+        return None
+    }
+
+    Some(truncate(text))
+}
+
+fn extract_pdf(bytes: &[u8]) -> Option<String> {
+    // pdf-extract panics on some malformed documents instead of erroring.
+    match panic::catch_unwind(AssertUnwindSafe(|| pdf_extract::extract_text_from_mem(bytes))) {
+        Ok(Ok(text)) => Some(text),
+        Ok(Err(err)) => {
+            debug!("Unable to extract text from PDF: {}", err);
+            None
+        },
+        Err(_) => {
+            debug!("PDF text extraction panicked!");
+            None
+        }
+    }
+}
+
+fn is_slide(name: &str) -> bool {
+    name.starts_with("ppt/slides/slide") && name.ends_with(".xml")
+}
+
+/// Collects the contents of every `text_tag` element from the archive parts
+/// selected by `include`, breaking lines at `paragraph_tag`.
+fn extract_office(
+    bytes: &[u8],
+    include: impl Fn(&str) -> bool,
+    text_tag: &[u8],
+    paragraph_tag: &[u8]
+) -> Option<String> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).ok()?;
+
+    // Slides are stored as slide1.xml, slide2.xml, ... and the archive order
+    // isn't guaranteed, so sort them numerically.
+    let mut parts: Vec<String> = archive.file_names()
+        .filter(|name| include(name))
+        .map(|name| name.to_string())
+        .collect();
+    parts.sort_by_key(|name| (name.len(), name.clone()));
+
+    let mut text = String::new();
+    let mut budget = MAX_XML_SIZE;
+    for part in parts {
+        // Read through a limit, the sizes in the archive can't be trusted.
+        let mut xml = Vec::new();
+        let read = archive.by_name(&part).ok()?
+            .take(budget as u64)
+            .read_to_end(&mut xml);
+
+        if read.is_err() {
+            continue
+        }
+
+        budget -= xml.len();
+        let xml: Cow<str> = String::from_utf8_lossy(&xml);
+        let mut reader = Reader::from_str(&xml);
+        let mut in_text = false;
+        loop {
+            match reader.read_event() {
+                Ok(Event::Start(tag)) if tag.name().as_ref() == text_tag => in_text = true,
+                Ok(Event::End(tag)) if tag.name().as_ref() == text_tag => in_text = false,
+                Ok(Event::End(tag)) if tag.name().as_ref() == paragraph_tag => text.push('\n'),
+                Ok(Event::Text(content)) if in_text => {
+                    if let Ok(content) = content.unescape() {
+                        text.push_str(&content);
+                    }
+                },
+                Ok(Event::Eof) | Err(_) => break,
+                _ => {}
+            }
+        }
+
+        if text.len() > MAX_TEXT_LENGTH || budget == 0 {
+            break
+        }
+    }
+
+    Some(text)
+}
+
+fn truncate(mut text: String) -> String {
+    if text.len() > MAX_TEXT_LENGTH {
+        let mut end = MAX_TEXT_LENGTH;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        text.truncate(end);
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    use super::{extract_text, MAX_XML_SIZE};
+
+    fn archive(parts: &[(&str, &str)]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, contents) in parts {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn extracts_docx_paragraphs() {
+        let docx = archive(&[(
+            "word/document.xml",
+            "<w:document><w:p><w:t>Hello</w:t></w:p><w:p><w:t>world &amp; more</w:t></w:p></w:document>"
+        )]);
+
+        assert_eq!(extract_text("docx", &docx).as_deref(), Some("Hello world & more"));
+    }
+
+    #[test]
+    fn extracts_pptx_slides_in_order() {
+        let pptx = archive(&[
+            ("ppt/slides/slide10.xml", "<p:sld><a:p><a:t>ten</a:t></a:p></p:sld>"),
+            ("ppt/slides/slide2.xml", "<p:sld><a:p><a:t>two</a:t></a:p></p:sld>"),
+            ("ppt/slides/slide1.xml", "<p:sld><a:p><a:t>one</a:t></a:p></p:sld>"),
+            ("ppt/notesSlides/notesSlide1.xml", "<a:p><a:t>notes</a:t></a:p>")
+        ]);
+
+        assert_eq!(extract_text("pptx", &pptx).as_deref(), Some("one two ten"));
+    }
+
+    #[test]
+    fn ignores_unsupported_or_empty_documents() {
+        assert_eq!(extract_text("txt", b"plain text"), None);
+        assert_eq!(extract_text("docx", b"not a zip"), None);
+        assert_eq!(extract_text("docx", &archive(&[("word/document.xml", "<w:document/>")])), None);
+    }
+
+    #[test]
+    fn stops_decompressing_large_documents() {
+        // Text past the first MAX_XML_SIZE bytes is never inflated.
+        let padding = "<w:r/>".repeat(MAX_XML_SIZE / 6 + 1);
+        let xml = format!("<w:document>{}<w:p><w:t>late</w:t></w:p></w:document>", padding);
+        let docx = archive(&[("word/document.xml", &xml)]);
+        assert!(docx.len() < MAX_XML_SIZE / 100);
+
+        assert_eq!(extract_text("docx", &docx), None);
+    }
+}
@@ -1,5 +1,8 @@
 pub(crate) mod schema;
 pub(crate) mod models;
+pub(crate) mod functions;
+pub mod migrations;
+pub mod retention;
 
 use crate::SharedState;
 
@@ -21,13 +24,9 @@ impl FromRequestParts<SharedState> for DatabaseConnection {
 
     async fn from_request_parts(_parts: &mut Parts, state: &SharedState) -> Result<Self, Self::Rejection> {
         let pool = &state.pool;
-        let conn = pool.get_owned().await;
-
-        if conn.is_err() {
-            Err(internal_error(ConverterError::DatabaseConnection("Unable to connect to database!")))
-        } else {
-            let conn = conn.unwrap();
-            Ok(Self(conn))
+        match pool.get_owned().await {
+            Ok(conn) => Ok(Self(conn)),
+            Err(_) => Err(internal_error(ConverterError::DatabaseConnection("Unable to connect to database!")))
         }
     }
 
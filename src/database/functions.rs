@@ -0,0 +1,15 @@
+use diesel::{expression::AsExpression, infix_operator, pg::Pg, sql_types::Text};
+
+infix_operator!(WordSimilar, " <% ", backend: Pg);
+
+/// `needle <% haystack`, true when `pg_trgm`'s word similarity is above
+/// `pg_trgm.word_similarity_threshold`. Written as the operator rather than
+/// a `word_similarity()` call so the trigram index on `files.file_name` is
+/// used.
+pub fn word_similar<N, H>(needle: N, haystack: H) -> WordSimilar<N::Expression, H::Expression>
+where
+    N: AsExpression<Text>,
+    H: AsExpression<Text>
+{
+    WordSimilar::new(needle.as_expression(), haystack.as_expression())
+}
@@ -0,0 +1,36 @@
+use anyhow::{anyhow, Result};
+use diesel::{pg::Pg, Connection};
+use diesel_async::{async_connection_wrapper::AsyncConnectionWrapper, AsyncPgConnection};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use tracing::info;
+
+/// Every migration under `migrations/`, compiled into the binary so a fresh
+/// database can be brought up without the Diesel CLI.
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+/// Applies any migrations that have not yet been run against `db_url`.
+///
+/// Diesel's migration harness is synchronous, so the work is moved onto a
+/// blocking thread rather than stalling the async runtime.
+pub async fn run_pending_migrations(db_url: String) -> Result<()> {
+    tokio::task::spawn_blocking(move || {
+        let mut conn = AsyncConnectionWrapper::<AsyncPgConnection>::establish(&db_url)?;
+        apply(&mut conn)
+    })
+    .await?
+}
+
+fn apply(conn: &mut impl MigrationHarness<Pg>) -> Result<()> {
+    let applied = conn.run_pending_migrations(MIGRATIONS)
+        .map_err(|err| anyhow!("Unable to run pending migrations: {}", err))?;
+
+    if applied.is_empty() {
+        info!("Database schema is up to date!");
+    }
+
+    for version in applied {
+        info!("Applied migration {}", version);
+    }
+
+    Ok(())
+}
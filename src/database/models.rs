@@ -1,17 +1,245 @@
+use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 
+use serde_json::Value;
+
+use crate::{converter::{formats, jobs::JobStatus}, webhook::inbox::InboxStatus};
+
 #[derive(Queryable, Selectable)]
 #[diesel(table_name = crate::database::schema::files)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct File {
     pub id: i32,
     pub file_name: String,
-    pub content: String
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+    pub size_bytes: i64,
+    pub mime_type: String,
+    pub source_file_name: Option<String>,
+    pub source_format: Option<String>,
+    pub target_format: String,
+    pub backend_job_id: Option<String>,
+    pub sha256: String,
+    pub has_preview: bool
+}
+
+impl File {
+
+    pub fn display_size(&self) -> String {
+        display_size(self.size_bytes)
+    }
+
+    pub fn display_created_at(&self) -> String {
+        display_timestamp(&self.created_at)
+    }
+
+    pub fn is_viewable(&self) -> bool {
+        formats::is_viewable(&self.mime_type)
+    }
+
+}
+
+/// A file with the session whose job produced it, for operators.
+pub struct OwnedFile {
+    pub file: FileSummary,
+    pub owner: Option<String>
+}
+
+impl OwnedFile {
+
+    pub fn display_owner(&self) -> String {
+        self.owner.as_deref().map(display_session).unwrap_or_default()
+    }
+
+}
+
+/// A file without its content, for listings that never need the bytes.
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = crate::database::schema::files)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct FileSummary {
+    pub id: i32,
+    pub file_name: String,
+    pub created_at: DateTime<Utc>,
+    pub size_bytes: i64,
+    pub mime_type: String,
+    pub target_format: String,
+    pub has_preview: bool
+}
+
+impl FileSummary {
+
+    pub fn display_size(&self) -> String {
+        display_size(self.size_bytes)
+    }
+
+    pub fn display_created_at(&self) -> String {
+        display_timestamp(&self.created_at)
+    }
+
+    pub fn is_viewable(&self) -> bool {
+        formats::is_viewable(&self.mime_type)
+    }
+
+}
+
+/// A conversion as recorded in `jobs`, which outlives the in-memory
+/// `PendingJob`.
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = crate::database::schema::jobs)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Job {
+    pub id: i32,
+    pub session_id: String,
+    pub source_file_name: String,
+    pub target_format: String,
+    pub status: String,
+    pub file_id: Option<i32>,
+    pub error: Option<String>,
+    pub attempts: i32,
+    pub has_input: bool,
+    pub credits: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>
+}
+
+impl Job {
+
+    pub fn status(&self) -> JobStatus {
+        JobStatus::parse(&self.status).unwrap_or(JobStatus::Failed)
+    }
+
+    /// Failed jobs can be submitted again while their upload is stored.
+    pub fn is_retryable(&self) -> bool {
+        self.status() == JobStatus::Failed && self.has_input
+    }
+
+    pub fn display_created_at(&self) -> String {
+        display_timestamp(&self.created_at)
+    }
+
+    pub fn display_updated_at(&self) -> String {
+        display_timestamp(&self.updated_at)
+    }
+
+    /// How long the job ran, or has been running so far.
+    pub fn display_duration(&self) -> String {
+        let end = match self.status().is_terminal() {
+            true => self.updated_at,
+            false => Utc::now()
+        };
+
+        let seconds = (end - self.created_at).num_seconds().max(0);
+        match seconds {
+            0..=59 => format!("{}s", seconds),
+            60..=3599 => format!("{}m {}s", seconds / 60, seconds % 60),
+            _ => format!("{}h {}m", seconds / 3600, seconds % 3600 / 60)
+        }
+    }
+
+    pub fn display_owner(&self) -> String {
+        display_session(&self.session_id)
+    }
+
+}
+
+/// A webhook request as stored in `webhook_inbox`.
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = crate::database::schema::webhook_inbox)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct InboxEntry {
+    pub id: i32,
+    pub endpoint: String,
+    pub headers: Value,
+    pub body: String,
+    pub status: String,
+    pub error: Option<String>,
+    pub attempts: i32,
+    pub received_at: DateTime<Utc>,
+    pub processed_at: Option<DateTime<Utc>>
+}
+
+impl InboxEntry {
+
+    pub fn status(&self) -> InboxStatus {
+        InboxStatus::parse(&self.status).unwrap_or(InboxStatus::Failed)
+    }
+
+    pub fn display_received_at(&self) -> String {
+        display_timestamp(&self.received_at)
+    }
+
+    pub fn display_processed_at(&self) -> String {
+        self.processed_at.as_ref().map(display_timestamp).unwrap_or_default()
+    }
+
+    /// The body indented for reading, or as it arrived when it isn't JSON.
+    pub fn pretty_body(&self) -> String {
+        serde_json::from_str::<Value>(&self.body)
+            .and_then(|body| serde_json::to_string_pretty(&body))
+            .unwrap_or_else(|_| self.body.clone())
+    }
+
+    pub fn pretty_headers(&self) -> String {
+        serde_json::to_string_pretty(&self.headers).unwrap_or_default()
+    }
+
+}
+
+/// Human readable file size, e.g. `1.4 MB`.
+pub(crate) fn display_size(size_bytes: i64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+
+    let mut size = size_bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", size_bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+fn display_timestamp(timestamp: &DateTime<Utc>) -> String {
+    timestamp.format("%Y-%m-%d %H:%M UTC").to_string()
+}
+
+/// The start of a session id. Ids are as good as the session cookie, so
+/// operators only ever see enough to tell sessions apart.
+pub(crate) fn display_session(session_id: &str) -> String {
+    let start: String = session_id.chars().take(8).collect();
+    format!("{}…", start)
 }
 
 #[derive(Insertable)]
 #[diesel(table_name = crate::database::schema::files)]
 pub struct NewFile<'de> {
     pub file_name: &'de str,
-    pub content: &'de str
+    pub content: &'de str,
+    pub size_bytes: i64,
+    pub mime_type: &'de str,
+    pub source_file_name: Option<&'de str>,
+    pub source_format: Option<&'de str>,
+    pub target_format: &'de str,
+    pub backend_job_id: Option<&'de str>,
+    pub sha256: &'de str,
+    pub cache_key: Option<&'de str>,
+    pub content_text: Option<&'de str>,
+    pub preview: Option<&'de str>
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::database::schema::jobs)]
+pub struct NewJob<'de> {
+    pub session_id: &'de str,
+    pub source_file_name: &'de str,
+    pub target_format: &'de str,
+    pub cache_key: Option<&'de str>,
+    /// The upload, base64 encoded, kept for retries.
+    pub input: Option<&'de str>,
+    pub priority: i16
 }
@@ -0,0 +1,65 @@
+use std::env;
+
+use chrono::{DateTime, Duration, Utc};
+use diesel::{ExpressionMethods, QueryDsl, QueryResult};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Deserialize;
+
+use crate::{
+    converter::jobs::JobStatus,
+    database::schema::{files, jobs, webhook_deliveries, webhook_inbox},
+    webhook::inbox::InboxStatus
+};
+
+/// How long converted files are kept when `FILE_RETENTION_DAYS` is unset.
+const DEFAULT_RETENTION_DAYS: i64 = 30;
+
+pub fn retention_days() -> i64 {
+    env::var("FILE_RETENTION_DAYS")
+        .ok()
+        .and_then(|days| days.parse::<i64>().ok())
+        .unwrap_or(DEFAULT_RETENTION_DAYS)
+}
+
+/// Files created before this instant have expired and must not be served
+/// from the conversion cache.
+pub fn cutoff() -> DateTime<Utc> {
+    Utc::now() - Duration::days(retention_days())
+}
+
+/// What [`purge`] deleted.
+#[derive(Deserialize)]
+pub struct Purged {
+    pub files: usize,
+    pub jobs: usize,
+    pub webhooks: usize
+}
+
+/// Deletes everything older than the retention period: converted files,
+/// jobs that ended and webhooks that need no further attention. Failed
+/// webhooks are kept until an operator looked at them.
+pub async fn purge(conn: &mut AsyncPgConnection) -> QueryResult<Purged> {
+    let cutoff = cutoff();
+
+    let files = diesel::delete(files::table.filter(files::created_at.lt(cutoff)))
+        .execute(conn)
+        .await?;
+
+    let jobs = diesel::delete(jobs::table)
+        .filter(jobs::status.eq_any([JobStatus::Finished.as_str(), JobStatus::Failed.as_str(), JobStatus::Cancelled.as_str()]))
+        .filter(jobs::updated_at.lt(cutoff))
+        .execute(conn)
+        .await?;
+
+    let webhooks = diesel::delete(webhook_inbox::table)
+        .filter(webhook_inbox::status.eq_any([InboxStatus::Processed.as_str(), InboxStatus::Ignored.as_str()]))
+        .filter(webhook_inbox::received_at.lt(cutoff))
+        .execute(conn)
+        .await?;
+
+    diesel::delete(webhook_deliveries::table.filter(webhook_deliveries::received_at.lt(cutoff)))
+        .execute(conn)
+        .await?;
+
+    Ok(Purged { files, jobs, webhooks })
+}
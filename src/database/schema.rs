@@ -1,9 +1,88 @@
 // @generated automatically by Diesel CLI.
 
+pub mod sql_types {
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "tsvector", schema = "pg_catalog"))]
+    pub struct Tsvector;
+}
+
 diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::Tsvector;
+
     files (id) {
         id -> Int4,
         file_name -> Varchar,
         content -> Text,
+        created_at -> Timestamptz,
+        size_bytes -> Int8,
+        mime_type -> Varchar,
+        source_file_name -> Nullable<Varchar>,
+        source_format -> Nullable<Varchar>,
+        target_format -> Varchar,
+        backend_job_id -> Nullable<Varchar>,
+        sha256 -> Varchar,
+        cache_key -> Nullable<Varchar>,
+        file_name_tsv -> Tsvector,
+        content_text -> Nullable<Text>,
+        content_tsv -> Tsvector,
+        preview -> Nullable<Text>,
+        has_preview -> Bool,
+    }
+}
+
+diesel::table! {
+    jobs (id) {
+        id -> Int4,
+        session_id -> Varchar,
+        backend_job_id -> Nullable<Varchar>,
+        source_file_name -> Varchar,
+        target_format -> Varchar,
+        status -> Varchar,
+        file_id -> Nullable<Int4>,
+        error -> Nullable<Text>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        cache_key -> Nullable<Varchar>,
+        notified_at -> Nullable<Timestamptz>,
+        input -> Nullable<Text>,
+        attempts -> Int4,
+        has_input -> Bool,
+        priority -> Int2,
+        credits -> Nullable<Int4>,
     }
 }
+
+diesel::table! {
+    webhook_deliveries (backend_job_id, event) {
+        backend_job_id -> Varchar,
+        event -> Varchar,
+        received_at -> Timestamptz,
+        processed_at -> Nullable<Timestamptz>,
+        file_id -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    webhook_inbox (id) {
+        id -> Int4,
+        endpoint -> Varchar,
+        headers -> Jsonb,
+        body -> Text,
+        status -> Varchar,
+        error -> Nullable<Text>,
+        attempts -> Int4,
+        received_at -> Timestamptz,
+        processed_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::joinable!(jobs -> files (file_id));
+diesel::joinable!(webhook_deliveries -> files (file_id));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    files,
+    jobs,
+    webhook_deliveries,
+    webhook_inbox,
+);
@@ -1,12 +1,17 @@
 pub(crate) mod index;
 pub(crate) mod file;
+pub(crate) mod job;
 pub(crate) mod download;
 pub(crate) mod search;
 pub(crate) mod api;
 pub(crate) mod webhooks;
 pub(crate) mod websocket;
+pub(crate) mod events;
+pub(crate) mod admin;
+pub(crate) mod metrics;
 
 use axum::{routing::get, Router};
+use sha2::{Digest, Sha256};
 use tower_http::services::ServeDir;
 
 use crate::SharedState;
@@ -14,17 +19,32 @@ use self::{
     index::index,
     file::file,
     download::download,
-    search::search
+    search::search,
+    job::job
 };
 
 pub fn get_router() -> Router<SharedState> {
     Router::new()
         .route("/", get(index))
         .route("/files/:id", get(file))
+        .route("/files/:id/view", get(file::view))
+        .route("/files/:id/preview", get(file::preview))
         .route("/download/:id", get(download))
         .route("/search", get(search))
         .route("/ws", get(websocket::socket))
+        .route("/events", get(events::session_events))
+        .route("/jobs", get(job::history))
+        .route("/jobs/:id", get(job))
+        .route("/jobs/:id/events", get(events::job_events))
+        .route("/metrics", get(metrics::metrics))
         .nest("/api", api::get_router())
         .nest("/webhooks", webhooks::get_router())
+        .nest("/admin", admin::get_router())
         .nest_service("/assets", ServeDir::new("static"))
 }
+
+/// Compares a secret to what a client sent. Comparing digests keeps the
+/// time taken independent of how much of the secret was right.
+pub(crate) fn constant_time_eq(secret: &str, given: &str) -> bool {
+    Sha256::digest(secret.as_bytes()) == Sha256::digest(given.as_bytes())
+}
@@ -0,0 +1,141 @@
+mod credits;
+mod files;
+mod jobs;
+mod overview;
+mod sessions;
+pub(crate) mod webhooks;
+
+use std::env;
+
+use askama::Template;
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Form, State},
+    http::request::Parts,
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Router
+};
+use serde::Deserialize;
+use tower_sessions::Session;
+use tracing::{error, info, warn};
+
+use crate::{templates::AdminLogin, SharedState};
+
+/// Session key set once the password was entered.
+const ADMIN_KEY: &str = "admin";
+
+pub(super) fn get_router() -> Router<SharedState> {
+    Router::new()
+        .route("/", get(overview::show))
+        .route("/purge", post(overview::purge))
+        .route("/jobs", get(jobs::list))
+        .route("/jobs/:id/cancel", post(jobs::cancel))
+        .route("/files", get(files::list))
+        .route("/files/:id/delete", post(files::delete))
+        .route("/sessions", get(sessions::list))
+        .route("/login", get(login_page).post(login))
+        .route("/logout", post(logout))
+        .route("/credits", get(credits::show))
+        .route("/credits/check", post(credits::check))
+        .route("/webhooks", get(webhooks::list))
+        .route("/webhooks/:id", get(webhooks::show))
+        .route("/webhooks/:id/replay", post(webhooks::replay))
+}
+
+/// Proof that the request comes from a logged in operator. Everyone else is
+/// sent to the login page.
+pub(crate) struct Admin;
+
+#[async_trait]
+impl FromRequestParts<SharedState> for Admin {
+
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &SharedState) -> Result<Self, Self::Rejection> {
+        let session = Session::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        match is_admin(&session).await {
+            true => Ok(Admin),
+            false => Err(Redirect::to("/admin/login").into_response())
+        }
+    }
+
+}
+
+/// Whether an operator is logged in on the session.
+pub(crate) async fn is_admin(session: &Session) -> bool {
+    matches!(session.get::<bool>(ADMIN_KEY).await, Ok(Some(true)))
+}
+
+async fn login_page() -> Html<String> {
+    let login = AdminLogin {
+        enabled: admin_password().is_some(),
+        failed: false
+    };
+
+    Html(login.render().unwrap())
+}
+
+#[derive(Deserialize)]
+struct LoginForm {
+    password: String
+}
+
+async fn login(State(state): State<SharedState>, session: Session, Form(form): Form<LoginForm>) -> Response {
+    let accepted = admin_password().is_some_and(|password| {
+        super::constant_time_eq(&password, &form.password)
+    });
+
+    if !accepted {
+        warn!("Rejected admin login!");
+        let login = AdminLogin {
+            enabled: admin_password().is_some(),
+            failed: true
+        };
+
+        return Html(login.render().unwrap()).into_response()
+    }
+
+    // A fresh id, so a session id known before logging in is worthless.
+    // Saving right away assigns it, so the session's jobs can follow it.
+    let previous_id = session.id();
+    let logged_in = match session.cycle_id().await {
+        Ok(()) => session.insert(ADMIN_KEY, true).await,
+        Err(err) => Err(err)
+    };
+
+    let logged_in = match logged_in {
+        Ok(()) => session.save().await,
+        Err(err) => Err(err)
+    };
+
+    if let Err(err) = logged_in {
+        error!("Unable to store admin login: {}", err);
+        return Redirect::to("/admin/login").into_response()
+    }
+
+    if let (Some(previous_id), Some(id)) = (previous_id, session.id()) {
+        state.move_session(&previous_id.to_string(), &id.to_string()).await;
+    }
+
+    info!("Admin logged in!");
+    Redirect::to("/admin").into_response()
+}
+
+async fn logout(session: Session) -> Redirect {
+    if let Err(err) = session.remove::<bool>(ADMIN_KEY).await {
+        error!("Unable to remove admin login: {}", err);
+    }
+
+    Redirect::to("/")
+}
+
+/// `ADMIN_PASSWORD`, without which nobody can log in.
+fn admin_password() -> Option<String> {
+    env::var("ADMIN_PASSWORD")
+        .ok()
+        .filter(|password| !password.is_empty())
+}
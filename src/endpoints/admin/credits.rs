@@ -0,0 +1,60 @@
+use askama::Template;
+use axum::{
+    extract::State,
+    response::{Html, IntoResponse, Redirect, Response}
+};
+use chrono::{TimeDelta, Utc};
+use tracing::error;
+
+use crate::{
+    converter::{credits, jobs},
+    database::DatabaseConnection,
+    errors::{internal_error, ConverterError},
+    templates::AdminCredits,
+    SharedState
+};
+
+use super::Admin;
+
+/// How many charged jobs the page lists.
+const RECENT_JOBS: i64 = 50;
+
+pub(crate) async fn show(
+    _admin: Admin,
+    State(state): State<SharedState>,
+    DatabaseConnection(mut conn): DatabaseConnection
+) -> Response {
+    let now = Utc::now();
+    let usage = async {
+        let used_today = jobs::credits_used_since(&mut conn, now - TimeDelta::days(1)).await?;
+        let used_month = jobs::credits_used_since(&mut conn, now - TimeDelta::days(30)).await?;
+        let jobs = jobs::recently_charged(&mut conn, RECENT_JOBS).await?;
+        Ok::<_, diesel::result::Error>((used_today, used_month, jobs))
+    };
+
+    match usage.await {
+        Ok((used_today, used_month, jobs)) => {
+            let page = AdminCredits {
+                reading: state.credits.reading().await,
+                low: state.credits.is_low().await,
+                threshold: state.credits.threshold,
+                action: state.credits.action,
+                used_today,
+                used_month,
+                jobs
+            };
+
+            Html(page.render().unwrap()).into_response()
+        },
+        Err(err) => {
+            error!("Unable to look up credit usage: {}", err);
+            internal_error(ConverterError::DatabaseConnection("Unable to look up credit usage!")).into_response()
+        }
+    }
+}
+
+/// Checks the balance right away instead of waiting for the monitor.
+pub(crate) async fn check(_admin: Admin, State(state): State<SharedState>) -> Redirect {
+    credits::check(&state).await;
+    Redirect::to("/admin/credits")
+}
@@ -0,0 +1,65 @@
+use askama::Template;
+use axum::{
+    extract::Path,
+    response::{Html, IntoResponse, Redirect, Response}
+};
+use diesel::{ExpressionMethods, NullableExpressionMethods, QueryDsl, SelectableHelper};
+use diesel_async::RunQueryDsl;
+use tracing::{error, info};
+
+use crate::{
+    database::{models::{FileSummary, OwnedFile}, schema::{files, jobs}, DatabaseConnection},
+    errors::{internal_error, ConverterError},
+    templates::AdminFiles
+};
+
+use super::Admin;
+
+/// How many files the page lists.
+const PAGE_SIZE: i64 = 100;
+
+pub(crate) async fn list(
+    _admin: Admin,
+    DatabaseConnection(mut conn): DatabaseConnection
+) -> Response {
+    // The owner is the session whose job produced the file. Files kept from
+    // before jobs were recorded have none.
+    let files = files::table
+        .left_join(jobs::table)
+        .order(files::created_at.desc())
+        .limit(PAGE_SIZE)
+        .select((FileSummary::as_select(), jobs::session_id.nullable()))
+        .load::<(FileSummary, Option<String>)>(&mut conn)
+        .await;
+
+    match files {
+        Ok(files) => {
+            let files = files.into_iter()
+                .map(|(file, owner)| OwnedFile { file, owner })
+                .collect();
+
+            Html(AdminFiles { files }.render().unwrap()).into_response()
+        },
+        Err(err) => {
+            error!("Unable to list files: {}", err);
+            internal_error(ConverterError::DatabaseConnection("Unable to list files!")).into_response()
+        }
+    }
+}
+
+pub(crate) async fn delete(
+    _admin: Admin,
+    DatabaseConnection(mut conn): DatabaseConnection,
+    Path(id): Path<i32>
+) -> Response {
+    match diesel::delete(files::table.filter(files::id.eq(id))).execute(&mut conn).await {
+        Ok(_) => {
+            info!("[File {}] Deleted by an admin!", id);
+            Redirect::to("/admin/files").into_response()
+        },
+        Err(err) => {
+            error!("[File {}] Unable to delete file: {}", id, err);
+            internal_error(ConverterError::DatabaseConnection("Unable to delete file!")).into_response()
+        }
+    }
+}
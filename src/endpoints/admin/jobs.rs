@@ -0,0 +1,64 @@
+use askama::Template;
+use axum::{
+    extract::{Path, Query, State},
+    response::{Html, IntoResponse, Redirect, Response}
+};
+use serde::Deserialize;
+use tracing::{error, info, warn};
+
+use crate::{
+    converter::jobs::{self, JobId, JobStatus},
+    database::DatabaseConnection,
+    errors::{internal_error, ConverterError},
+    templates::AdminJobs,
+    SharedState
+};
+
+use super::Admin;
+
+/// How many jobs the page lists.
+const PAGE_SIZE: i64 = 100;
+
+#[derive(Deserialize)]
+pub(crate) struct JobsQuery {
+    status: Option<String>
+}
+
+pub(crate) async fn list(
+    _admin: Admin,
+    DatabaseConnection(mut conn): DatabaseConnection,
+    Query(query): Query<JobsQuery>
+) -> Response {
+    let status = query.status.as_deref().and_then(JobStatus::parse);
+    match jobs::recent(&mut conn, status, PAGE_SIZE).await {
+        Ok(jobs) => {
+            let page = AdminJobs {
+                jobs,
+                status,
+                statuses: JobStatus::ALL
+            };
+
+            Html(page.render().unwrap()).into_response()
+        },
+        Err(err) => {
+            error!("Unable to list jobs: {}", err);
+            internal_error(ConverterError::DatabaseConnection("Unable to list jobs!")).into_response()
+        }
+    }
+}
+
+/// Cancels any session's job.
+pub(crate) async fn cancel(
+    _admin: Admin,
+    State(state): State<SharedState>,
+    DatabaseConnection(mut conn): DatabaseConnection,
+    Path(id): Path<i32>
+) -> Redirect {
+    let job_id = JobId::from(id);
+    match jobs::cancel(&state, &mut conn, &job_id, None).await {
+        Ok(()) => info!("[{}] Job was cancelled by an admin!", job_id.0),
+        Err(err) => warn!("[{}] Admin could not cancel job: {}", job_id.0, err.message())
+    }
+
+    Redirect::to("/admin/jobs")
+}
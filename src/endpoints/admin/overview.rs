@@ -0,0 +1,86 @@
+use askama::Template;
+use axum::{
+    extract::{Query, State},
+    response::{Html, IntoResponse, Redirect, Response}
+};
+use diesel::{dsl::{count_star, sql}, sql_types::BigInt, QueryDsl};
+use diesel_async::RunQueryDsl;
+use tracing::{error, info};
+
+use crate::{
+    converter::jobs::JobStatus,
+    database::{retention::{self, Purged}, schema::{files, jobs}, DatabaseConnection},
+    errors::{internal_error, ConverterError},
+    templates::AdminOverview,
+    SharedState
+};
+
+use super::Admin;
+
+pub(crate) async fn show(
+    _admin: Admin,
+    State(state): State<SharedState>,
+    DatabaseConnection(mut conn): DatabaseConnection,
+    purged: Option<Query<Purged>>
+) -> Response {
+    let counts = async {
+        let jobs = jobs::table
+            .group_by(jobs::status)
+            .select((jobs::status, count_star()))
+            .load::<(String, i64)>(&mut conn)
+            .await?;
+
+        let files = files::table
+            .select((count_star(), sql::<BigInt>("COALESCE(SUM(size_bytes), 0)::BIGINT")))
+            .first::<(i64, i64)>(&mut conn)
+            .await?;
+
+        Ok::<_, diesel::result::Error>((jobs, files))
+    };
+
+    let (jobs, (file_count, file_bytes)) = match counts.await {
+        Ok(counts) => counts,
+        Err(err) => {
+            error!("Unable to count jobs and files: {}", err);
+            return internal_error(ConverterError::DatabaseConnection("Unable to load the overview!")).into_response()
+        }
+    };
+
+    let jobs_by_status = JobStatus::ALL.into_iter()
+        .map(|status| {
+            let count = jobs.iter()
+                .find(|(candidate, _)| candidate == status.as_str())
+                .map_or(0, |(_, count)| *count);
+
+            (status, count)
+        })
+        .collect();
+
+    let page = AdminOverview {
+        jobs_by_status,
+        file_count,
+        file_bytes,
+        sessions: state.connected_sessions().await.len(),
+        retention_days: retention::retention_days(),
+        purged: purged.map(|Query(purged)| purged)
+    };
+
+    Html(page.render().unwrap()).into_response()
+}
+
+/// Deletes everything past the retention period right away.
+pub(crate) async fn purge(
+    _admin: Admin,
+    DatabaseConnection(mut conn): DatabaseConnection
+) -> Response {
+    match retention::purge(&mut conn).await {
+        Ok(Purged { files, jobs, webhooks }) => {
+            info!("Purged {} files, {} jobs and {} webhooks!", files, jobs, webhooks);
+            Redirect::to(&format!("/admin?files={}&jobs={}&webhooks={}", files, jobs, webhooks)).into_response()
+        },
+        Err(err) => {
+            error!("Unable to purge expired data: {}", err);
+            internal_error(ConverterError::DatabaseConnection("Unable to purge expired data!")).into_response()
+        }
+    }
+}
@@ -0,0 +1,15 @@
+use askama::Template;
+use axum::{extract::State, response::Html};
+
+use crate::{templates::AdminSessions, SharedState};
+
+use super::Admin;
+
+/// Sessions with a socket or event stream open right now.
+pub(crate) async fn list(_admin: Admin, State(state): State<SharedState>) -> Html<String> {
+    let page = AdminSessions {
+        sessions: state.connected_sessions().await
+    };
+
+    Html(page.render().unwrap())
+}
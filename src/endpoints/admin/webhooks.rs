@@ -0,0 +1,95 @@
+use askama::Template;
+use axum::{
+    extract::{Path, Query, State},
+    response::{Html, IntoResponse, Redirect, Response}
+};
+use serde::Deserialize;
+use tracing::{error, info};
+
+use crate::{
+    database::DatabaseConnection,
+    endpoints::webhooks,
+    errors::{internal_error, ConverterError},
+    templates::{AdminWebhook, AdminWebhooks, NotFound},
+    webhook::{inbox::{self, InboxStatus}, WebhookEvent},
+    SharedState
+};
+
+use super::Admin;
+
+/// How many webhooks the inbox lists.
+const PAGE_SIZE: i64 = 100;
+
+#[derive(Deserialize)]
+pub(crate) struct InboxQuery {
+    status: Option<String>
+}
+
+pub(crate) async fn list(
+    _admin: Admin,
+    DatabaseConnection(mut conn): DatabaseConnection,
+    Query(query): Query<InboxQuery>
+) -> Response {
+    let status = query.status.as_deref().and_then(InboxStatus::parse);
+    match inbox::list(&mut conn, status, PAGE_SIZE).await {
+        Ok(entries) => {
+            let page = AdminWebhooks {
+                entries,
+                status,
+                statuses: InboxStatus::ALL
+            };
+
+            Html(page.render().unwrap()).into_response()
+        },
+        Err(err) => {
+            error!("Unable to list webhooks: {}", err);
+            internal_error(ConverterError::DatabaseConnection("Unable to list webhooks!")).into_response()
+        }
+    }
+}
+
+pub(crate) async fn show(
+    _admin: Admin,
+    DatabaseConnection(mut conn): DatabaseConnection,
+    Path(id): Path<i32>
+) -> Response {
+    match inbox::find(&mut conn, id).await {
+        Ok(Some(entry)) => Html(AdminWebhook { entry }.render().unwrap()).into_response(),
+        Ok(None) => Html(NotFound {}.render().unwrap()).into_response(),
+        Err(err) => {
+            error!("[Webhook {}] Unable to look up webhook: {}", id, err);
+            internal_error(ConverterError::DatabaseConnection("Unable to look up webhook!")).into_response()
+        }
+    }
+}
+
+/// Runs a stored webhook through the same logic as a fresh delivery.
+pub(crate) async fn replay(
+    _admin: Admin,
+    State(state): State<SharedState>,
+    DatabaseConnection(mut conn): DatabaseConnection,
+    Path(id): Path<i32>
+) -> Response {
+    let entry = match inbox::find(&mut conn, id).await {
+        Ok(Some(entry)) => entry,
+        Ok(None) => return Html(NotFound {}.render().unwrap()).into_response(),
+        Err(err) => {
+            error!("[Webhook {}] Unable to look up webhook: {}", id, err);
+            return internal_error(ConverterError::DatabaseConnection("Unable to look up webhook!")).into_response()
+        }
+    };
+
+    let Some(event) = WebhookEvent::from_endpoint(&entry.endpoint) else {
+        return internal_error(ConverterError::Rejected("Webhooks for this endpoint can't be replayed!")).into_response()
+    };
+
+    if let Err(err) = inbox::retry(&mut conn, id).await {
+        error!("[Webhook {}] Unable to count replay: {}", id, err);
+        return internal_error(ConverterError::DatabaseConnection("Unable to replay webhook!")).into_response()
+    }
+
+    info!("[Webhook {}] Replaying webhook!", id);
+    webhooks::accept(&state, &mut conn, id, event, &entry.body, true).await;
+
+    Redirect::to(&format!("/admin/webhooks/{}", id)).into_response()
+}
@@ -1,7 +1,12 @@
 pub mod convert;
+pub mod jobs;
 pub mod search;
 
-use axum::{routing::post, Router};
+use axum::{
+    http::{header, HeaderMap},
+    routing::post,
+    Router
+};
 use convert::convert;
 use search::search;
 
@@ -9,4 +14,14 @@ pub fn get_router() -> Router<crate::SharedState> {
     Router::new()
         .route("/convert", post(convert))
         .route("/search", post(search))
+        .route("/jobs/:id/cancel", post(jobs::cancel))
+        .route("/jobs/:id/retry", post(jobs::retry))
+}
+
+/// Our scripts post with `fetch` and show the returned message, while a
+/// plain form post without JavaScript navigates and wants a page back.
+pub(crate) fn wants_page(headers: &HeaderMap) -> bool {
+    headers.get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"))
 }
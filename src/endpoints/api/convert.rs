@@ -1,156 +1,257 @@
-use std::{env, net::SocketAddr};
+use std::net::SocketAddr;
 
-use axum::extract::{ConnectInfo, Multipart, State};
+use axum::{
+    extract::{multipart::{Field, MultipartError}, ConnectInfo, Multipart, State},
+    http::HeaderMap,
+    response::{IntoResponse, Redirect, Response}
+};
+use diesel_async::AsyncPgConnection;
+
+use super::wants_page;
 use base64::{engine::general_purpose::STANDARD, Engine};
 use hyper::StatusCode;
-use serde_json::{json, Value};
 use tower_sessions::Session;
-use tracing::{debug, info};
+use tracing::{debug, error, info, warn};
 
 use crate::{
-    converter::jobs::JobId, errors::{internal_error,ConverterError}, response::CreateResponse
+    converter::{cache, credits::LowCreditAction, formats::{self, UploadError, SIGNATURE_LENGTH}, jobs::{self, JobId, PendingJob}, queue::Priority, submit, text},
+    database::{models::NewJob, DatabaseConnection}, endpoints::admin::is_admin, errors::{internal_error,ConverterError}, metrics::Metrics,
+    protocol::ServerMessage, scanner::ScanResult
 };
 
+/// What became of an accepted upload.
+enum Submitted {
+    /// The same conversion was done before and the file is ready.
+    Cached(i32),
+    Queued(JobId),
+    /// Queued, but held back until CloudConvert credits are topped up.
+    Held(JobId)
+}
+
 pub async fn convert(
+    headers: HeaderMap,
     session: Session,
     State(state): State<crate::SharedState>,
+    DatabaseConnection(mut conn): DatabaseConnection,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    form: Multipart
+) -> Response {
+    let wants_page = wants_page(&headers);
+
+    match submit(session, state, &mut conn, addr, form).await {
+        Ok(Submitted::Cached(file_id)) if wants_page => Redirect::to(&format!("/files/{}", file_id)).into_response(),
+        Ok(Submitted::Queued(job_id) | Submitted::Held(job_id)) if wants_page => Redirect::to(&format!("/jobs/{}", job_id.0)).into_response(),
+        Ok(Submitted::Cached(_)) => (StatusCode::OK, "This file has already been converted, you will be redirected shortly.").into_response(),
+        Ok(Submitted::Queued(_)) => (StatusCode::OK, "You will be redirected when your file(s) have completed converting.").into_response(),
+        Ok(Submitted::Held(_)) => (StatusCode::OK, "Our converter is very busy right now, your file will be converted as soon as possible. You will be redirected once it is done.").into_response(),
+        Err(err) => err.into_response()
+    }
+}
+
+async fn submit(
+    session: Session,
+    state: crate::SharedState,
+    conn: &mut AsyncPgConnection,
+    addr: SocketAddr,
     mut form: Multipart
-) -> (StatusCode, String) {
+) -> Result<Submitted, (StatusCode, String)> {
     let mut input_file_name: Option<String> = None;
-    let mut input_file_contents: Option<String> = None;
+    let mut input_file_contents: Option<Vec<u8>> = None;
     let mut conversion_type: Option<String> = None;
     
     // If session_id is null, there is something wrong on the clients' end.
     if session.id().is_none() {
-        return internal_error(ConverterError::Convert("You seem to be missing a session id! Please reload your browser."));   
+        return Err(internal_error(ConverterError::Convert("You seem to be missing a session id! Please reload your browser.")));   
     }
 
     let session_id = session.id().unwrap();
 
     info!("[{}] Recieved POST request on /convert", addr);
 
-    while let Some(field) = form.next_field().await.unwrap() {
-        let name = field.name().unwrap();
+    while let Some(field) = form.next_field().await.map_err(multipart_error)? {
+        let name = field.name().unwrap_or_default();
         match name {
             "input_file" => {
-                input_file_name = Some(field.file_name().unwrap().to_string());
-                let bytes = &field.bytes().await.unwrap().to_vec();
-                input_file_contents = Some(STANDARD.encode(bytes));
+                let Some(file_name) = field.file_name().map(str::to_string) else {
+                    info!("[{}] Rejected upload without a file name", addr);
+                    return Err(internal_error(ConverterError::BadRequest("Your upload is missing its file name!")))
+                };
+
+                let bytes = match read_upload(&file_name, field).await {
+                    Ok(bytes) => bytes,
+                    Err(err) => {
+                        info!("[{}] Rejected upload {}: {}", addr, file_name, err.1);
+                        return Err(err)
+                    }
+                };
+
+                input_file_contents = Some(bytes);
+                input_file_name = Some(file_name);
             },
-            "conversion_type" => conversion_type = Some(field.text().await.unwrap()),
+            "conversion_type" => conversion_type = Some(field.text().await.map_err(multipart_error)?),
             _ => continue,
         }
     }
 
     if input_file_contents.is_none() || input_file_name.is_none() {
         info!("[{}] Could not find input file...", addr);
-        return internal_error(ConverterError::MissingDependencies("You need to upload a file!"))
+        return Err(internal_error(ConverterError::MissingDependencies("You need to upload a file!")))
     }
 
     if conversion_type.is_none() {
         info!("[{}] Could not find conversion type...", addr);
-        return internal_error(ConverterError::MissingDependencies("You need to upload a file!"))
+        return Err(internal_error(ConverterError::MissingDependencies("You need to upload a file!")))
+    }
+
+    if !formats::is_target_format(conversion_type.as_deref().unwrap_or_default()) {
+        info!("[{}] Rejected conversion to {:?}", addr, conversion_type);
+        return Err(internal_error(ConverterError::UnsupportedMediaType("Files can't be converted to that format!")))
     }
 
     info!("[{}] POST request passed depenceny checks...", addr);
 
-    let api_key = env::var("API_KEY")
-        .expect("API_KEY must be set! Check your .env file!");
+    let input_file_contents = input_file_contents.unwrap();
+    match state.scanner.scan(&input_file_contents).await {
+        Ok(ScanResult::Clean) => {},
+        Ok(ScanResult::Infected(signature)) => {
+            warn!("[{}] Rejected upload {:?}, virus scan found {}", addr, input_file_name, signature);
+            return Err(internal_error(ConverterError::Rejected("Your file was flagged by our virus scanner and can't be converted!")))
+        },
+        Err(err) => {
+            error!("[{}] Unable to scan upload: {}", addr, err);
+            return Err(internal_error(ConverterError::ServiceUnavailable("We couldn't scan your file right now, please try again later!")))
+        }
+    }
 
     let conversion_type = conversion_type.unwrap();
     let input_file_name = input_file_name.unwrap();
 
-    info!("[{}] Starting POST request to CloudConvert...", addr);
-    let client = reqwest::Client::new();
+    Metrics::add(&state.metrics.upload_bytes, input_file_contents.len() as u64);
+
+    let convert_options = submit::convert_options(&conversion_type);
+    let cache_key = cache::cache_key(&input_file_contents, &conversion_type, &convert_options);
+    match cache::find(conn, &cache_key).await {
+        Ok(Some(file_id)) => {
+            let hits = Metrics::increment(&state.metrics.cache_hits);
+            info!("[{}] Serving {} from file {} (cache hits: {})", addr, input_file_name, file_id, hits);
+            state.metrics.conversions.increment(&[&formats::extension(&input_file_name), &conversion_type]);
+
+            state.notify(&session_id.to_string(), ServerMessage::JobCompleted {
+                job_id: format!("cache-{}", file_id),
+                file_id
+            }).await;
+
+            return Ok(Submitted::Cached(file_id))
+        },
+        Ok(None) => {
+            let misses = Metrics::increment(&state.metrics.cache_misses);
+            debug!("[{}] No cached conversion for {} (cache misses: {})", addr, input_file_name, misses);
+        },
+        Err(err) => {
+            error!("[{}] Unable to look up cached conversion: {}", addr, err);
+        }
+    }
+
+    let held = state.credits.is_low().await;
+    if held && state.credits.action == LowCreditAction::Refuse {
+        warn!("[{}] Refused {}, CloudConvert credits are low!", addr, input_file_name);
+        return Err(internal_error(ConverterError::ServiceUnavailable("We can't convert files right now, please try again later!")))
+    }
+
+    state.metrics.conversions.increment(&[&formats::extension(&input_file_name), &conversion_type]);
 
-    let dev_mode = env::var("DEV_MODE")
-        .expect("DEV_MODE must be set! Check your .env file!")
-        .parse::<bool>()
-        .expect("DEV_MODE must be true/false! Check your .env file!");
+    let source_text = text::extract(formats::extension(&input_file_name), input_file_contents.clone()).await;
 
-    let base_url = match dev_mode {
-        false => env::var("CLOUDCONVERT_API").expect("CLOUDCONVERT_API must be set! Check your .env file!"),
-        true  => env::var("CLOUDCONVERT_SANDBOX_API").expect("CLOUDCONVERT_SANDBOX_API must be set! Check your .env file!") 
+    let priority = match is_admin(&session).await {
+        true => Priority::Staff,
+        false => Priority::Standard
     };
 
-    let job_response = client.post(format!("{}/v2/jobs", base_url))
-        .bearer_auth(&api_key)
-        .json(&json!({
-            "tasks": {
-                "import-my-file": {
-                    "operation": "import/base64",
-                    "file": input_file_contents.unwrap(),
-                    "filename": input_file_name,
-                },
-
-                "convert-my-file": {
-                    "operation": "convert",
-                    "input": "import-my-file",
-                    "output_format": conversion_type,
-                },
-
-                "export-my-file": {
-                    "operation": "export/url",
-                    "input": "convert-my-file"
-                }
-            },
-            "redirect": true
-        }))
-        .send()
-        .await
-        .unwrap();
-    
-    info!("[{}] Recieved response from CloudConvert!", addr);
-    debug!("[{}] Response: {:?}", addr, job_response);
-    match job_response.error_for_status() {
-        Ok(job_response) => {
-            match job_response.status() {
-                StatusCode::CREATED => {
-                    let bytes = job_response
-                        .bytes()
-                        .await;
-                    match bytes {
-                        Ok(bytes) => {
-                            let response_string = String::from_utf8(bytes.to_vec()).unwrap();
-                            debug!("{}", response_string);
-
-                            let body: Value = serde_json::from_str(&response_string).unwrap();
-                            let body = body["data"].clone();
-
-                            let response = serde_json::from_value::<CreateResponse>(body);
-                            if response.is_err() {
-                                debug!("[{}] No 'data' field in response!", addr);
-                                return internal_error(ConverterError::Convert("Something went wrong while trying to convert the requested file!"))
-                            }
-
-                            let response = response.unwrap();
-                            let job_id = JobId::from(response.id);
-                            
-                            info!("{}", session_id);
-
-                            let mut pending = state.pending_jobs.write().await;
-                            pending.insert(job_id, session_id.to_string());
-                            drop(pending);
-                             
-                            (StatusCode::OK, "You will be redirected when your file(s) have completed converting.".to_string())
-                        },
-                        Err(err) => {
-                            debug!("[{}] Error while reading bytes: {}", addr, err);
-                            internal_error(ConverterError::Convert("Something went wrong while trying to convert the requested file!"))
-                        }
-                    } 
-                }
-                code => {
-                    info!("[{}] Recieved the wrong status code from CloudConvert: {}", addr, code);
-                    internal_error(ConverterError::Convert("Something went wrong while trying to convert the requested file!"))
-                }
-            }
+    let input = STANDARD.encode(&input_file_contents);
+    let job_id = jobs::create(conn, &NewJob {
+        session_id: &session_id.to_string(),
+        source_file_name: &input_file_name,
+        target_format: &conversion_type,
+        cache_key: Some(&cache_key),
+        input: Some(&input),
+        priority: priority.value()
+    }).await;
 
-        },
-        Err(_) => {
-            info!("[{}] Recieved error from CloudConvert job!", addr);
-            internal_error(ConverterError::Convert("Something went wrong while trying to convert the requested file!"))
+    let job_id = match job_id {
+        Ok(job_id) => job_id,
+        Err(err) => {
+            error!("[{}] Unable to record job: {}", addr, err);
+            return Err(internal_error(ConverterError::DatabaseConnection("Something went wrong while trying to convert the requested file!")))
         }
+    };
+
+    let queued = ServerMessage::JobQueued {
+        job_id: job_id.0.clone(),
+        file_name: input_file_name.clone()
+    };
+
+    state.pending_jobs.write().await.insert(job_id.clone(), PendingJob {
+        session_id: session_id.to_string(),
+        source_file_name: input_file_name.clone(),
+        target_format: conversion_type,
+        cache_key,
+        source_text,
+        events: Vec::new(),
+        submitted_at: None
+    });
+
+    state.publish(&job_id, queued).await;
+
+    info!("[{}] Queued {} as job {}", addr, input_file_name, job_id.0);
+    state.submissions.wake();
+
+    match held {
+        true => Ok(Submitted::Held(job_id)),
+        false => Ok(Submitted::Queued(job_id))
+    }
+}
+
+/// Streams an uploaded file into memory, rejecting it as soon as its content
+/// signature doesn't match the claimed extension or it grows past the limit
+/// for its format.
+async fn read_upload(file_name: &str, mut field: Field<'_>) -> Result<Vec<u8>, (StatusCode, String)> {
+    let format = formats::upload_format(file_name).map_err(upload_error)?;
+
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut sniffed = false;
+    loop {
+        let chunk = match field.chunk().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(err) => return Err(multipart_error(err))
+        };
+
+        bytes.extend_from_slice(&chunk);
+        format.check_size(bytes.len()).map_err(upload_error)?;
+
+        if !sniffed && bytes.len() >= SIGNATURE_LENGTH {
+            format.check_signature(&bytes).map_err(upload_error)?;
+            sniffed = true;
+        }
+    }
+
+    format.check_content(&bytes).map_err(upload_error)?;
+    Ok(bytes)
+}
+
+fn upload_error(err: UploadError) -> (StatusCode, String) {
+    let message = err.message();
+    match err {
+        UploadError::TooLarge(..) => internal_error(ConverterError::PayloadTooLarge(&message)),
+        _ => internal_error(ConverterError::UnsupportedMediaType(&message))
+    }
+}
+
+/// A form that could not be read, either because it grew past the body limit
+/// or because the upload broke off.
+fn multipart_error(err: MultipartError) -> (StatusCode, String) {
+    match err.status() {
+        StatusCode::PAYLOAD_TOO_LARGE => internal_error(ConverterError::PayloadTooLarge("Your upload is too large!")),
+        _ => internal_error(ConverterError::BadRequest("Your upload was interrupted, please try again!"))
     }
 }
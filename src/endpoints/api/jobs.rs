@@ -0,0 +1,82 @@
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect, Response}
+};
+use tower_sessions::Session;
+use tracing::info;
+
+use crate::{
+    converter::{jobs::{self, CancelError, JobId}, queue::{self, RetryError}},
+    database::DatabaseConnection,
+    errors::{internal_error, ConverterError},
+    SharedState
+};
+
+use super::wants_page;
+
+pub async fn cancel(
+    headers: HeaderMap,
+    session: Session,
+    State(state): State<SharedState>,
+    DatabaseConnection(mut conn): DatabaseConnection,
+    Path(identifier): Path<String>
+) -> Response {
+    let job_id = JobId(identifier);
+    let Some(session_id) = session.id() else {
+        return internal_error(ConverterError::NotFound(CancelError::NotFound.message())).into_response()
+    };
+
+    info!("[{}] Recieved cancel request for job {}", session_id, job_id.0);
+    let cancelled = jobs::cancel(&state, &mut conn, &job_id, Some(&session_id.to_string())).await;
+    if wants_page(&headers) {
+        return Redirect::to(&format!("/jobs/{}", job_id.0)).into_response()
+    }
+
+    match cancelled {
+        Ok(()) => (StatusCode::OK, "Your conversion was cancelled.").into_response(),
+        Err(err) => {
+            let message = err.message();
+            let err = match err {
+                CancelError::NotFound => ConverterError::NotFound(message),
+                CancelError::AlreadyEnded => ConverterError::Conflict(message),
+                CancelError::Backend => ConverterError::ServiceUnavailable(message)
+            };
+
+            internal_error(err).into_response()
+        }
+    }
+}
+
+pub async fn retry(
+    headers: HeaderMap,
+    session: Session,
+    State(state): State<SharedState>,
+    DatabaseConnection(mut conn): DatabaseConnection,
+    Path(identifier): Path<String>
+) -> Response {
+    let job_id = JobId(identifier);
+    let Some(session_id) = session.id() else {
+        return internal_error(ConverterError::NotFound(RetryError::NotFound.message())).into_response()
+    };
+
+    info!("[{}] Recieved retry request for job {}", session_id, job_id.0);
+    let retried = queue::retry(&state, &mut conn, &job_id, &session_id.to_string()).await;
+    if wants_page(&headers) {
+        return Redirect::to(&format!("/jobs/{}", job_id.0)).into_response()
+    }
+
+    match retried {
+        Ok(()) => (StatusCode::OK, "You will be redirected when your file(s) have completed converting.").into_response(),
+        Err(err) => {
+            let message = err.message();
+            let err = match err {
+                RetryError::NotFound => ConverterError::NotFound(message),
+                RetryError::NotRetryable => ConverterError::Conflict(message),
+                RetryError::Unavailable => ConverterError::DatabaseConnection(message)
+            };
+
+            internal_error(err).into_response()
+        }
+    }
+}
@@ -2,21 +2,92 @@ use std::net::SocketAddr;
 
 use askama::Template;
 use axum::{extract::ConnectInfo, response::{Html, IntoResponse, Response}, Form};
-use diesel::{PgTextExpressionMethods, QueryDsl, SelectableHelper};
+use chrono::{Days, NaiveDate};
+use diesel::{
+    dsl::sql, sql_types::{Bool, Float4, Nullable, Text}, BoolExpressionMethods, ExpressionMethods, PgTextExpressionMethods,
+    QueryDsl, SelectableHelper
+};
 use diesel_async::RunQueryDsl;
 use serde::Deserialize;
 use tracing::info;
 
-use crate::{database::{models::File, DatabaseConnection}, templates::SearchResults};
+use crate::{
+    database::{functions::word_similar, models::FileSummary, DatabaseConnection},
+    templates::{Highlight, SearchHit, SearchResults}
+};
 use crate::database::schema::files::dsl::{
     files as Files,
-    file_name
+    id,
+    file_name,
+    created_at,
+    size_bytes,
+    target_format
 };
 
+/// Number of files returned per page of results.
+const PAGE_SIZE: i64 = 20;
+
+/// The deepest page of results served, larger page numbers get this one.
+const MAX_PAGE: i64 = 1000;
+
+/// Control characters used to delimit matches in `ts_headline` output, so
+/// the snippet can be HTML escaped before the highlights are added.
+const SNIPPET_START: char = '\u{2}';
+const SNIPPET_END: char = '\u{3}';
+
 #[derive(Deserialize)]
 pub struct SearchQuery {
     #[serde(rename = "search-term")]
-    pub search_term: String
+    pub search_term: String,
+
+    /// Restricts results to a single target format; empty means any.
+    #[serde(default)]
+    pub format: String,
+
+    /// Earliest creation date to include, as `YYYY-MM-DD`.
+    #[serde(default)]
+    pub from: String,
+
+    /// Latest creation date to include, as `YYYY-MM-DD`.
+    #[serde(default)]
+    pub to: String,
+
+    #[serde(default)]
+    pub sort: SortBy,
+
+    #[serde(default)]
+    pub order: SortOrder,
+
+    #[serde(default = "first_page")]
+    pub page: i64
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SortBy {
+    /// Best match first, or newest first when there is no search term.
+    #[default]
+    Relevance,
+    Name,
+    Date,
+    Size
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc
+}
+
+fn first_page() -> i64 {
+    1
+}
+
+/// The page to serve for a requested page number, which can be anything.
+fn page_number(requested: i64) -> i64 {
+    requested.clamp(first_page(), MAX_PAGE)
 }
 
 pub async fn search(
@@ -26,19 +97,213 @@ pub async fn search(
 ) -> Response {
     info!("[{}] Recieved POST request on /api/search!", addr);
 
+    let page = page_number(query.page);
+    let term = query.search_term.trim();
+
+    // Up to two fragments of the document text around the matched words,
+    // which are wrapped in SNIPPET_START and SNIPPET_END.
+    let snippet = sql::<Nullable<Text>>("CASE WHEN ")
+        .bind::<Text, _>(term.to_string())
+        .sql(" = '' THEN NULL ELSE ts_headline('english', files.content_text, plainto_tsquery('english', ")
+        .bind::<Text, _>(term.to_string())
+        .sql("), ")
+        .bind::<Text, _>(format!(
+            "StartSel={}, StopSel={}, MaxWords=30, MinWords=10, MaxFragments=2, FragmentDelimiter=\" … \"",
+            SNIPPET_START, SNIPPET_END
+        ))
+        .sql(") END");
+
+    let mut files = Files
+        .select((FileSummary::as_select(), snippet))
+        .into_boxed();
+
+    // Full-text matches whole words, trigram similarity forgives typos and
+    // the substring match keeps partial words like "pres" working. The
+    // document text is matched with English stemming.
+    if !term.is_empty() {
+        files = files.filter(
+            sql::<Bool>("files.file_name_tsv @@ plainto_tsquery('simple', ")
+                .bind::<Text, _>(term.to_string())
+                .sql(")")
+                .or(word_similar(term.to_string(), file_name))
+                .or(file_name.ilike(format!("%{}%", term)))
+                .or(
+                    sql::<Bool>("files.content_tsv @@ plainto_tsquery('english', ")
+                        .bind::<Text, _>(term.to_string())
+                        .sql(")")
+                )
+        );
+    }
+
+    if !query.format.is_empty() {
+        files = files.filter(target_format.eq(query.format.to_lowercase()));
+    }
+
+    if let Ok(from) = NaiveDate::parse_from_str(&query.from, "%Y-%m-%d") {
+        files = files.filter(created_at.ge(from.and_hms_opt(0, 0, 0).unwrap().and_utc()));
+    }
 
-    let filter = format!("%{}%", &query.search_term);
+    // `to` is inclusive, so everything before the start of the next day matches.
+    if let Some(to) = NaiveDate::parse_from_str(&query.to, "%Y-%m-%d").ok().and_then(|to| to.checked_add_days(Days::new(1))) {
+        files = files.filter(created_at.lt(to.and_hms_opt(0, 0, 0).unwrap().and_utc()));
+    }
 
-    let files = Files
-        .filter(file_name.ilike(filter))
-        .select(File::as_select())
+    files = match (&query.sort, &query.order) {
+        (SortBy::Relevance, _) if !term.is_empty() => {
+            // Best of the trigram word similarity and the full-text ranks,
+            // with matches in the name counting for more than in the text.
+            let rank = sql::<Float4>("GREATEST(word_similarity(")
+                .bind::<Text, _>(term.to_string())
+                .sql(", files.file_name), ts_rank(files.file_name_tsv, plainto_tsquery('simple', ")
+                .bind::<Text, _>(term.to_string())
+                .sql(")), ts_rank(files.content_tsv, plainto_tsquery('english', ")
+                .bind::<Text, _>(term.to_string())
+                .sql(")) * 0.5)");
+
+            files.order((rank.desc(), created_at.desc(), id.desc()))
+        },
+        (SortBy::Relevance, _) => files.order((created_at.desc(), id.desc())),
+        (SortBy::Name, SortOrder::Asc) => files.order((file_name.asc(), id.asc())),
+        (SortBy::Name, SortOrder::Desc) => files.order((file_name.desc(), id.desc())),
+        (SortBy::Date, SortOrder::Asc) => files.order((created_at.asc(), id.asc())),
+        (SortBy::Date, SortOrder::Desc) => files.order((created_at.desc(), id.desc())),
+        (SortBy::Size, SortOrder::Asc) => files.order((size_bytes.asc(), id.asc())),
+        (SortBy::Size, SortOrder::Desc) => files.order((size_bytes.desc(), id.desc()))
+    };
+
+    // One extra row tells us whether another page exists.
+    let mut files: Vec<(FileSummary, Option<String>)> = files
+        .offset((page - 1) * PAGE_SIZE)
+        .limit(PAGE_SIZE + 1)
         .get_results(&mut conn)
-        .await;
+        .await
+        .unwrap_or_default();
+
+    let has_more = files.len() as i64 > PAGE_SIZE;
+    files.truncate(PAGE_SIZE as usize);
+
+    let files = files.into_iter()
+        .map(|(file, snippet)| SearchHit {
+            name: highlight(&file.file_name, term),
+            snippet: snippet.map(|snippet| parse_snippet(&snippet)).unwrap_or_default(),
+            file
+        })
+        .collect();
 
     let search_results = SearchResults {
-        files: files.unwrap_or(Vec::new()),
-        search_term: query.search_term
+        files,
+        search_term: query.search_term.clone(),
+        first_page: page == 1,
+        next_page: if has_more && page < MAX_PAGE { Some(page + 1) } else { None }
     };
     
     Html(search_results.render().unwrap()).into_response()
 }
+
+/// Splits a `ts_headline` snippet into segments, or returns nothing when the
+/// snippet contains no match (e.g. only the file name matched).
+fn parse_snippet(snippet: &str) -> Vec<Highlight> {
+    if !snippet.contains(SNIPPET_START) {
+        return Vec::new()
+    }
+
+    let mut segments: Vec<Highlight> = Vec::new();
+    for (index, part) in snippet.split(SNIPPET_START).enumerate() {
+        // Every part but the first starts with a match.
+        let (matched, rest) = match part.split_once(SNIPPET_END) {
+            Some((matched, rest)) if index > 0 => (Some(matched), rest),
+            _ => (None, part)
+        };
+
+        if let Some(matched) = matched {
+            segments.push(Highlight { text: matched.to_string(), matched: true });
+        }
+
+        if !rest.is_empty() {
+            segments.push(Highlight { text: rest.to_string(), matched: false });
+        }
+    }
+
+    segments
+}
+
+/// Splits `text` into segments, marking every case-insensitive occurrence of
+/// a word from `term`.
+fn highlight(text: &str, term: &str) -> Vec<Highlight> {
+    if text.is_empty() {
+        return Vec::new()
+    }
+
+    let haystack = text.to_ascii_lowercase();
+    let mut matched = vec![false; text.len()];
+
+    for word in term.split_whitespace().map(|word| word.to_ascii_lowercase()) {
+        for (start, _) in haystack.match_indices(&word) {
+            matched[start..(start + word.len())].fill(true);
+        }
+    }
+
+    let mut segments: Vec<Highlight> = Vec::new();
+    let mut start = 0;
+    for (index, _) in text.char_indices().skip(1).chain([(text.len(), ' ')]) {
+        if index == text.len() || matched[index] != matched[start] {
+            segments.push(Highlight {
+                text: text[start..index].to_string(),
+                matched: matched[start]
+            });
+            start = index;
+        }
+    }
+
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{highlight, page_number, parse_snippet, MAX_PAGE, SNIPPET_END, SNIPPET_START};
+    use crate::templates::Highlight;
+
+    fn segments(highlights: Vec<Highlight>) -> Vec<(String, bool)> {
+        highlights.into_iter().map(|highlight| (highlight.text, highlight.matched)).collect()
+    }
+
+    #[test]
+    fn highlights_every_word_of_the_term() {
+        assert_eq!(segments(highlight("Annual Report 2024.pdf", "report annual")), [
+            ("Annual".to_string(), true),
+            (" ".to_string(), false),
+            ("Report".to_string(), true),
+            (" 2024.pdf".to_string(), false)
+        ]);
+    }
+
+    #[test]
+    fn highlights_around_multibyte_characters() {
+        assert_eq!(segments(highlight("Über uns.docx", "uns")), [
+            ("Über ".to_string(), false),
+            ("uns".to_string(), true),
+            (".docx".to_string(), false)
+        ]);
+        assert!(highlight("", "term").is_empty());
+    }
+
+    #[test]
+    fn splits_snippets_at_delimiters() {
+        let snippet = format!("the {}quick{} fox{}jumps{}", SNIPPET_START, SNIPPET_END, SNIPPET_START, SNIPPET_END);
+        assert_eq!(segments(parse_snippet(&snippet)), [
+            ("the ".to_string(), false),
+            ("quick".to_string(), true),
+            (" fox".to_string(), false),
+            ("jumps".to_string(), true)
+        ]);
+        assert!(parse_snippet("no match here").is_empty());
+    }
+
+    #[test]
+    fn clamps_page_numbers() {
+        assert_eq!(page_number(3), 3);
+        assert_eq!(page_number(0), 1);
+        assert_eq!(page_number(i64::MIN), 1);
+        assert_eq!(page_number(i64::MAX), MAX_PAGE);
+    }
+}
@@ -4,17 +4,21 @@ use diesel_async::RunQueryDsl;
 use askama::Template;
 use tracing::debug;
 use axum::{
-    body::Body, extract::{ConnectInfo, Path}, http::{header, HeaderName}, response::{AppendHeaders, Html, IntoResponse}
+    body::Body, extract::{ConnectInfo, Path, Query, State}, http::{header, HeaderName}, response::{AppendHeaders, Html, IntoResponse}
 };
+use serde::Deserialize;
 
 use base64::{engine::general_purpose::STANDARD, Engine};
 use crate::{
+    converter::formats,
     database::{
         DatabaseConnection,
         schema::files::dsl::files,
         models::File
     },
-    templates::NotFound
+    metrics::Metrics,
+    templates::NotFound,
+    SharedState
 };
 
 pub enum DownloadResponse {
@@ -39,10 +43,26 @@ impl IntoResponse for DownloadResponse {
 
 }
 
+#[derive(Deserialize)]
+pub struct DownloadQuery {
+    /// `?inline=1` shows PDFs and images in the browser instead of saving them.
+    pub inline: Option<String>
+}
+
+impl DownloadQuery {
+
+    fn is_inline(&self) -> bool {
+        matches!(self.inline.as_deref(), Some("1") | Some("true"))
+    }
+
+}
+
 pub async fn download(
+    State(state): State<SharedState>,
     DatabaseConnection(mut conn): DatabaseConnection,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
-    Path(identifier): Path<i32>
+    Path(identifier): Path<i32>,
+    Query(query): Query<DownloadQuery>
 ) -> DownloadResponse {
     let file: Result<File, _> = files
         .select(File::as_select())
@@ -52,32 +72,27 @@ pub async fn download(
 
     debug!("[{}] Attempting to find file {} in database!", addr, identifier);
     match file {
-        Ok(file) => DownloadResponse::Ok(start_download(file.content, file.file_name)),
+        Ok(file) => {
+            // Anything a browser can't display is still sent as an attachment.
+            let inline = query.is_inline() && formats::is_viewable(&file.mime_type);
+            Metrics::add(&state.metrics.download_bytes, file.size_bytes as u64);
+            DownloadResponse::Ok(start_download(file.content, file.file_name, file.mime_type, inline))
+        },
         Err(_) => DownloadResponse::NotFound
     }
 }
 
 fn start_download(
     base64: String,
-    file_name: String
+    file_name: String,
+    mime_type: String,
+    inline: bool
 ) -> (AppendHeaders<Vec<(HeaderName, String)>>, Body) {
-    let file_extension = &file_name[(file_name.rfind('.').unwrap_or(0) + 1)..file_name.len()];
-    let mime_type = match file_extension {
-        "pdf" => {
-            "application/pdf"
-        },
-        "docx" => {
-            "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
-        },
-        "pptx" => {
-            "application/vnd.openxmlformats-officedocument.presentationml.presentation"
-        }
-        _ => panic!("Could not download file of type {}", file_extension)
-    };
-
+    let disposition = if inline { "inline" } else { "attachment" };
     let headers: AppendHeaders<Vec<(HeaderName, String)>> = AppendHeaders([
-        (header::CONTENT_TYPE, format!("{}; charset=utf-8", mime_type)),
-        (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", file_name))
+        (header::CONTENT_TYPE, mime_type),
+        (header::CONTENT_DISPOSITION, format!("{}; filename=\"{}\"", disposition, file_name)),
+        (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string())
     ].to_vec());
 
     let bytes = STANDARD.decode(base64)
@@ -0,0 +1,195 @@
+use std::{collections::VecDeque, convert::Infallible};
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{sse::{Event as SseEvent, KeepAlive, Sse}, IntoResponse, Response}
+};
+use futures::stream::{self, Stream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tower_sessions::Session;
+use tracing::{error, info, warn};
+
+use crate::{
+    converter::jobs::{self, JobId},
+    database::DatabaseConnection,
+    protocol::Event,
+    SharedState
+};
+
+/// Streams every job event of the session over SSE, for clients whose
+/// proxies break websockets.
+pub(super) async fn session_events(
+    State(state): State<SharedState>,
+    session: Session,
+    headers: HeaderMap
+) -> Response {
+    let session_id = match session.id() {
+        Some(id) => id.to_string(),
+        None => return StatusCode::UNAUTHORIZED.into_response()
+    };
+
+    info!("[{}] Recieved event stream connection!", session_id);
+    stream_events(state, session_id, None, last_event_id(&headers)).await
+}
+
+/// Streams the events of a single job, ending after its final one.
+pub(super) async fn job_events(
+    State(state): State<SharedState>,
+    session: Session,
+    DatabaseConnection(mut conn): DatabaseConnection,
+    headers: HeaderMap,
+    Path(id): Path<String>
+) -> Response {
+    let session_id = match session.id() {
+        Some(id) => id.to_string(),
+        None => return StatusCode::UNAUTHORIZED.into_response()
+    };
+
+    let job_id = JobId(id);
+    let pending = state.pending_jobs.read().await
+        .get(&job_id)
+        .map(|job| job.session_id == session_id);
+
+    // Jobs are forgotten once they end, and after a restart, so the final
+    // event a reconnecting client missed is read from `jobs`.
+    match pending {
+        Some(true) => {},
+        Some(false) => {
+            warn!("[{}] Job {} of another session asked for!", session_id, job_id.0);
+            return StatusCode::NOT_FOUND.into_response()
+        },
+        None => match jobs::find(&mut conn, &job_id, &session_id).await {
+            Ok(Some(job)) => if let Some(message) = jobs::final_message(&job) {
+                if let Err(err) = jobs::mark_notified(&mut conn, &job_id).await {
+                    error!("[{}] Unable to record notification: {}", job_id.0, err);
+                }
+
+                info!("[{}] Sending final event of job {}!", session_id, job_id.0);
+                let event = to_sse(state.next_event(message));
+                return Sse::new(stream::iter([Ok::<_, Infallible>(event)])).into_response()
+            },
+            Ok(None) => {
+                warn!("[{}] No job {} for event stream!", session_id, job_id.0);
+                return StatusCode::NOT_FOUND.into_response()
+            },
+            Err(err) => {
+                error!("[{}] Unable to look up job: {}", job_id.0, err);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+
+    info!("[{}] Recieved event stream connection for job {}!", session_id, job_id.0);
+    stream_events(state, session_id, Some(job_id), last_event_id(&headers)).await
+}
+
+async fn stream_events(
+    state: SharedState,
+    session_id: String,
+    job_id: Option<JobId>,
+    after: Option<u64>
+) -> Response {
+    // Subscribe before replaying, so nothing published in between is lost.
+    let receiver = state.subscribe(&session_id).await;
+    let mut backlog = state.replay(&session_id, job_id.as_ref(), after).await;
+    if job_id.is_none() {
+        backlog.extend(state.undelivered(&session_id).await);
+    }
+
+    let subscription = Subscription {
+        state,
+        session_id,
+        job_id,
+        backlog: backlog.into(),
+        receiver: Some(receiver),
+        finished: false
+    };
+
+    Sse::new(into_stream(subscription))
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+fn into_stream(subscription: Subscription) -> impl Stream<Item = Result<SseEvent, Infallible>> {
+    stream::unfold(subscription, |mut subscription| async move {
+        let event = subscription.next().await?;
+        Some((Ok(to_sse(event)), subscription))
+    })
+}
+
+fn to_sse(event: Event) -> SseEvent {
+    SseEvent::default()
+        .id(event.id.to_string())
+        .data(event.message.to_json())
+}
+
+/// `Last-Event-ID`, sent by `EventSource` when it reconnects.
+fn last_event_id(headers: &HeaderMap) -> Option<u64> {
+    headers.get("last-event-id")
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.parse().ok())
+}
+
+struct Subscription {
+    state: SharedState,
+    session_id: String,
+    job_id: Option<JobId>,
+    backlog: VecDeque<Event>,
+    receiver: Option<broadcast::Receiver<Event>>,
+    finished: bool
+}
+
+impl Subscription {
+
+    async fn next(&mut self) -> Option<Event> {
+        if self.finished {
+            return None
+        }
+
+        let event = match self.backlog.pop_front() {
+            Some(event) => event,
+            None => {
+                let receiver = self.receiver.as_mut()?;
+                loop {
+                    match receiver.recv().await {
+                        Ok(event) if is_for_job(&event, self.job_id.as_ref()) => break event,
+                        Ok(_) => continue,
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!("[{}] Event stream fell behind and missed {} events!", self.session_id, skipped);
+                            continue
+                        },
+                        Err(RecvError::Closed) => return None
+                    }
+                }
+            }
+        };
+
+        self.finished = self.job_id.is_some() && event.message.is_terminal();
+        Some(event)
+    }
+
+}
+
+impl Drop for Subscription {
+
+    fn drop(&mut self) {
+        // The receiver has to be gone before the session's entry is checked.
+        drop(self.receiver.take());
+
+        let state = self.state.clone();
+        let session_id = std::mem::take(&mut self.session_id);
+        tokio::spawn(async move {
+            state.unsubscribe(&session_id).await;
+            info!("[{}] Event stream disconnected!", session_id);
+        });
+    }
+
+}
+
+fn is_for_job(event: &Event, job_id: Option<&JobId>) -> bool {
+    match job_id {
+        Some(job_id) => event.message.job_id() == Some(job_id.0.as_str()),
+        None => true
+    }
+}
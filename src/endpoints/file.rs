@@ -1,16 +1,19 @@
 use askama::Template;
-use diesel::{QueryDsl,  SelectableHelper};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use diesel::{OptionalExtension, QueryDsl,  SelectableHelper};
 use diesel_async::RunQueryDsl;
 
-use axum::{extract::Path, response::Html};
+use axum::{extract::Path, http::header, response::{Html, IntoResponse, Response}};
+use hyper::StatusCode;
 use crate::{
     database::{
-        models::File,
-        schema::files::dsl::files,
+        models::{File, FileSummary},
+        schema::files::dsl::{files, preview as file_preview},
         DatabaseConnection
     },
     templates::{
         FileInfo,
+        FileViewer,
         NotFound
     }
 };
@@ -32,7 +35,7 @@ pub async fn file(
             // If found, return download page with sufficient information
             let file_info = FileInfo {
                 download_uri: format!("/download/{identifier}"),
-                file_name: file.file_name
+                file
             };
 
             Html(file_info.render().unwrap())
@@ -43,3 +46,58 @@ pub async fn file(
         }
     }
 }
+
+pub async fn view(
+    DatabaseConnection(mut conn): DatabaseConnection,
+    Path(identifier): Path<i32>
+) -> Html<String> {
+    let file: Result<FileSummary, _> = files
+        .select(FileSummary::as_select())
+        .find(identifier)
+        .first(&mut conn)
+        .await;
+
+    match file {
+        Ok(file) => {
+            let viewer = FileViewer {
+                view_uri: format!("/download/{identifier}?inline=1"),
+                download_uri: format!("/download/{identifier}"),
+                file
+            };
+
+            Html(viewer.render().unwrap())
+        },
+        Err(_) => {
+            let not_found = NotFound {};
+            Html(not_found.render().unwrap())
+        }
+    }
+}
+
+pub async fn preview(
+    DatabaseConnection(mut conn): DatabaseConnection,
+    Path(identifier): Path<i32>
+) -> Response {
+    let preview: Result<Option<Option<String>>, _> = files
+        .select(file_preview)
+        .find(identifier)
+        .first(&mut conn)
+        .await
+        .optional();
+
+    match preview {
+        Ok(Some(Some(preview))) => match STANDARD.decode(preview) {
+            Ok(bytes) => (
+                [
+                    (header::CONTENT_TYPE, "image/png"),
+                    (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+                    (header::CACHE_CONTROL, "public, max-age=86400")
+                ],
+                bytes
+            ).into_response(),
+            Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        },
+        Ok(_) => StatusCode::NOT_FOUND.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    }
+}
@@ -5,18 +5,7 @@ use axum::{extract::ConnectInfo, response::Html};
 use tower_sessions::Session;
 use tracing::info;
 
-use crate::templates::{Index, NotFound};
-
-const AUTHORIZED_EXTENSIONS: [&str; 8] = [
-    ".jpg",
-    ".jpeg",
-    ".png",
-    ".ppt",
-    ".pptx",
-    ".doc",
-    ".docx",
-    ".pdf"
-];
+use crate::{converter::formats, templates::{Index, NotFound}};
 
 pub async fn index(
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
@@ -50,13 +39,10 @@ pub async fn index(
         }
     }
 
-    let id = session.id().unwrap();
-
     let website_url = env::var("WEBSITE_URL").expect("Website URL must be set!");
 
     let index_template = Index {
-        authorized_extensions: AUTHORIZED_EXTENSIONS.join(","),
-        session_id: id.to_string(),
+        authorized_extensions: formats::authorized_extensions().join(","),
         website_url
     };
 
@@ -0,0 +1,91 @@
+use askama::Template;
+use axum::{extract::{Path, State}, response::Html};
+use tower_sessions::Session;
+use tracing::error;
+
+use crate::{
+    converter::jobs::{self, JobId},
+    database::DatabaseConnection,
+    templates::{History, JobPage, NotFound},
+    SharedState
+};
+
+/// How often the job page reloads itself while the job is running.
+const REFRESH_SECONDS: u32 = 3;
+
+/// How many jobs the history page lists.
+const HISTORY_LENGTH: i64 = 50;
+
+pub async fn job(
+    State(state): State<SharedState>,
+    session: Session,
+    DatabaseConnection(mut conn): DatabaseConnection,
+    Path(identifier): Path<String>
+) -> Html<String> {
+    let job_id = JobId(identifier);
+
+    // Jobs are only shown to the session that started them.
+    let job = match session.id() {
+        Some(session_id) => jobs::find(&mut conn, &job_id, &session_id.to_string()).await,
+        None => Ok(None)
+    };
+
+    match job {
+        Ok(Some(job)) => {
+            if job.status().is_terminal() {
+                if let Err(err) = jobs::mark_notified(&mut conn, &job_id).await {
+                    error!("[{}] Unable to record notification: {}", job_id.0, err);
+                }
+            }
+
+            let (stage, position) = state.pending_jobs.read().await
+                .get(&job_id)
+                .map(|pending_job| (pending_job.stage(), pending_job.position()))
+                .unwrap_or_default();
+
+            let job_page = JobPage {
+                job,
+                stage,
+                position,
+                refresh_seconds: REFRESH_SECONDS
+            };
+
+            Html(job_page.render().unwrap())
+        },
+        Ok(None) => Html(NotFound {}.render().unwrap()),
+        Err(err) => {
+            error!("[{}] Unable to look up job: {}", job_id.0, err);
+            Html(NotFound {}.render().unwrap())
+        }
+    }
+}
+
+/// Lists the session's recent jobs. Visiting it counts as being told how
+/// every listed job ended.
+pub async fn history(
+    session: Session,
+    DatabaseConnection(mut conn): DatabaseConnection
+) -> Html<String> {
+    let jobs = match session.id() {
+        Some(session_id) => {
+            let session_id = session_id.to_string();
+            if let Err(err) = jobs::take_undelivered(&mut conn, &session_id).await {
+                error!("[{}] Unable to mark jobs as delivered: {}", session_id, err);
+            }
+
+            jobs::history(&mut conn, &session_id, HISTORY_LENGTH).await.unwrap_or_else(|err| {
+                error!("[{}] Unable to look up job history: {}", session_id, err);
+                Vec::new()
+            })
+        },
+        None => Vec::new()
+    };
+
+    let history = History {
+        in_progress: jobs.iter().any(|job| !job.status().is_terminal()),
+        jobs,
+        refresh_seconds: REFRESH_SECONDS
+    };
+
+    Html(history.render().unwrap())
+}
@@ -0,0 +1,35 @@
+use std::env;
+
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response}
+};
+use tracing::warn;
+
+use crate::SharedState;
+
+/// The Prometheus text format. When `METRICS_TOKEN` is set, scrapers have
+/// to send it as a bearer token.
+pub async fn metrics(State(state): State<SharedState>, headers: HeaderMap) -> Response {
+    if let Some(token) = metrics_token() {
+        let given = headers.get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or_default();
+
+        if !super::constant_time_eq(&token, given) {
+            warn!("Rejected metrics scrape without a valid token!");
+            return StatusCode::UNAUTHORIZED.into_response()
+        }
+    }
+
+    let body = crate::metrics::render(&state).await;
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response()
+}
+
+fn metrics_token() -> Option<String> {
+    env::var("METRICS_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty())
+}
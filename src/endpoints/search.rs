@@ -1,9 +1,11 @@
 use askama::Template;
 use axum::response::{Html, IntoResponse};
 
-use crate::templates::Search;
+use crate::templates::{Search, SEARCH_FORMATS};
 
 pub async fn search() -> impl IntoResponse {
-    let search = Search {};
+    let search = Search {
+        formats: SEARCH_FORMATS
+    };
     Html(search.render().unwrap())
 }
@@ -1,12 +1,305 @@
-use axum::{Router,routing::post};
+use axum::{extract::DefaultBodyLimit, http::{HeaderMap, StatusCode}, response::{IntoResponse, Response}, Json, Router, routing::post};
+use diesel_async::AsyncPgConnection;
+use serde_json::{json, Value};
+use tracing::{error, info, warn};
 
-use crate::SharedState;
+use crate::{
+    converter::jobs::{self, JobStatus, Submission},
+    metrics::Metrics,
+    response::{Job, JobTask},
+    webhook::{self, deliveries::{self, Delivery}, inbox::{self, InboxStatus}, Pushed, QueuedJob, WebhookEvent},
+    JobId, SharedState, State
+};
 
 pub(crate) mod finished;
 pub(crate) mod waiting;
 pub(crate) mod failed;
 
+/// The largest webhook body we accept. CloudConvert's are a few kilobytes.
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
 pub(super) fn get_router() -> Router<SharedState> {
     Router::new()
         .route("/finished", post(finished::finished))
+        .route("/failed", post(failed::failed))
+        .layer(DefaultBodyLimit::max(MAX_BODY_SIZE))
+}
+
+/// Checks, stores and queues a webhook for `event`, answering CloudConvert.
+async fn receive(
+    state: &SharedState,
+    conn: &mut AsyncPgConnection,
+    event: WebhookEvent,
+    headers: &HeaderMap,
+    body: &str
+) -> Response {
+    // Anyone can reach these endpoints, only what CloudConvert signed is
+    // looked at or kept.
+    if !webhook::is_signed(headers, body) {
+        warn!("Rejected webhook without a valid signature!");
+        return (StatusCode::UNAUTHORIZED, json(false)).into_response()
+    }
+
+    // Everything is stored before it is looked at, so nothing CloudConvert
+    // sends us is lost, not even payloads we fail to understand.
+    let inbox_id = match inbox::store(conn, event.endpoint(), headers, body).await {
+        Ok(inbox_id) => inbox_id,
+        Err(err) => {
+            error!("Unable to store webhook: {}", err);
+            return (StatusCode::INTERNAL_SERVER_ERROR, json(false)).into_response()
+        }
+    };
+
+    match accept(state, conn, inbox_id, event, body, false).await {
+        Outcome::Queued => json(true),
+        Outcome::Duplicate(file_id) => Json(json!({
+            "ok": true,
+            "file_id": file_id
+        })).into_response(),
+        Outcome::Ignored(_) | Outcome::Failed(_) => json(false),
+        Outcome::Busy => (StatusCode::SERVICE_UNAVAILABLE, json(false)).into_response(),
+        Outcome::Unavailable => (StatusCode::INTERNAL_SERVER_ERROR, json(false)).into_response()
+    }
+}
+
+/// What became of a webhook body handed to [`accept`].
+pub(crate) enum Outcome {
+    /// Waiting for a worker to process it.
+    Queued,
+    /// An earlier delivery of the same event already produced this file.
+    Duplicate(Option<i32>),
+    Ignored(String),
+    /// The body can't be processed as it is.
+    Failed(String),
+    /// The worker queue is full, try again later.
+    Busy,
+    /// The database could not record the delivery.
+    Unavailable
+}
+
+/// Parses a stored webhook body and queues it for a worker, recording the
+/// outcome in the inbox. Used for fresh deliveries and admin replays alike;
+/// a replay may reprocess a delivery whose first attempt stored nothing.
+pub(crate) async fn accept(
+    state: &SharedState,
+    conn: &mut AsyncPgConnection,
+    inbox_id: i32,
+    event: WebhookEvent,
+    body: &str,
+    replay: bool
+) -> Outcome {
+    let outcome = parse(conn, event, body, replay).await;
+    let outcome = match outcome {
+        Ok(job) => {
+            // Results are fetched and stored by a worker, so CloudConvert
+            // isn't kept waiting on our downloads.
+            let backend_job_id = job.id.clone();
+            match state.webhooks.push(QueuedJob { inbox_id, event, job, replay }) {
+                Pushed::Queued => Outcome::Queued,
+                Pushed::AlreadyQueued => {
+                    info!("[Job {}] Another delivery is already being processed!", backend_job_id);
+                    Outcome::Duplicate(None)
+                },
+                Pushed::Full => {
+                    error!("Webhook queue is full, asking CloudConvert to retry!");
+                    if let Err(err) = deliveries::forget(conn, &backend_job_id, event.name()).await {
+                        error!("[Job {}] Unable to forget delivery: {}", backend_job_id, err);
+                    }
+
+                    Outcome::Busy
+                }
+            }
+        },
+        Err(outcome) => outcome
+    };
+
+    let (status, error) = match &outcome {
+        Outcome::Queued => (InboxStatus::Queued, None),
+        Outcome::Duplicate(_) => (InboxStatus::Ignored, Some("Repeated delivery")),
+        Outcome::Ignored(reason) => (InboxStatus::Ignored, Some(reason.as_str())),
+        Outcome::Failed(reason) => (InboxStatus::Failed, Some(reason.as_str())),
+        Outcome::Busy => (InboxStatus::Failed, Some("The webhook queue was full")),
+        Outcome::Unavailable => (InboxStatus::Failed, Some("The delivery could not be recorded"))
+    };
+
+    if let Err(err) = inbox::set_status(conn, inbox_id, status, error).await {
+        error!("[Webhook {}] Unable to record outcome: {}", inbox_id, err);
+    }
+
+    outcome
+}
+
+async fn parse(conn: &mut AsyncPgConnection, event: WebhookEvent, body: &str, replay: bool) -> Result<Job, Outcome> {
+    let body = match serde_json::from_str::<Value>(body) {
+        Ok(body) => body,
+        Err(err) => {
+            warn!("Recieved malformed webhook: {}", err);
+            return Err(Outcome::Failed(format!("Malformed JSON: {}", err)))
+        }
+    };
+
+    {
+        if body["event"] != Value::String(event.name().to_string()) {
+            warn!("Recieved {} event on /webhooks/{}", body["event"], event.endpoint());
+            return Err(Outcome::Ignored(format!("Unexpected event {}", body["event"])))
+        }
+
+        info!("Recieved \"{}\" event!", event.name());
+    }
+
+    let job = body["job"].clone();
+    let job = serde_json::from_value::<Job>(job);
+    if let Err(err) = job {
+        warn!("Recieved error when attempting to unwrap job: {}", err);
+        return Err(Outcome::Failed(format!("Unable to read job: {}", err)));
+    }
+
+    let job = job.unwrap();
+    info!("[Job {}] Recieved {} response!", job.id, event.name());
+
+    // CloudConvert retries webhooks it thinks we missed, those must not be
+    // stored or announced a second time.
+    match deliveries::record(conn, &job.id, event.name()).await {
+        Ok(Delivery::New) => Ok(job),
+        Ok(Delivery::Duplicate { file_id: None }) if replay => {
+            info!("[Job {}] Replaying delivery that stored nothing!", job.id);
+            Ok(job)
+        },
+        Ok(Delivery::Duplicate { file_id }) => {
+            info!("[Job {}] Ignoring repeated delivery!", job.id);
+            Err(Outcome::Duplicate(file_id))
+        },
+        Err(err) => {
+            error!("[Job {}] Unable to record delivery: {}", job.id, err);
+            Err(Outcome::Unavailable)
+        }
+    }
+}
+
+/// Finds the job a webhook is about and how far it got. The tag only carries
+/// our sequential id, so the webhook must also name the job CloudConvert
+/// gave us when it was submitted.
+async fn submitted_job(conn: &mut AsyncPgConnection, job: &Job) -> Result<(JobId, Submission), String> {
+    let (job_id, attempt) = match &job.tag {
+        Some(tag) => (JobId::from_tag(tag), JobId::attempt_from_tag(tag)),
+        None => {
+            warn!("[Job {}] Webhook carries no tag, we did not start this job!", job.id);
+            return Err("The job carries no tag".to_string())
+        }
+    };
+
+    let mut submission = jobs::submission(conn, &job_id).await;
+
+    // Quick conversions can finish before their submission was recorded.
+    // The tag then names the attempt still waiting for its id.
+    if let Ok(Some(pending)) = &submission {
+        if pending.backend_job_id.is_none() && attempt == Some(pending.attempts) {
+            match jobs::submitted(conn, &job_id, &job.id).await {
+                Ok(true) => info!("[{}] Webhook came before the submission of {} was recorded!", job_id.0, job.id),
+                Ok(false) => {},
+                Err(err) => error!("[{}] Unable to record submission: {}", job_id.0, err)
+            }
+
+            submission = jobs::submission(conn, &job_id).await;
+        }
+    }
+
+    match submission {
+        Ok(Some(submission)) if submission.backend_job_id.as_deref() == Some(job.id.as_str()) => Ok((job_id, submission)),
+        Ok(Some(_)) => {
+            warn!("[{}] Webhook names job {}, which we did not submit!", job_id.0, job.id);
+            Err(format!("The job was not submitted as {}", job.id))
+        },
+        Ok(None) => {
+            warn!("[{}] Webhook is for a job we don't know!", job_id.0);
+            Err("The job does not exist".to_string())
+        },
+        Err(err) => {
+            error!("[{}] Unable to look up job: {}", job_id.0, err);
+            Err("The job could not be looked up".to_string())
+        }
+    }
+}
+
+/// Records what CloudConvert charged for the job's tasks. Failed and
+/// cancelled jobs are charged all the same, but only once however often
+/// their webhook is processed.
+async fn charge(state: &State, conn: &mut AsyncPgConnection, job_id: &JobId, tasks: &[JobTask]) {
+    let credits: i32 = tasks.iter().filter_map(|task| task.credits).sum();
+    if credits <= 0 {
+        return
+    }
+
+    match jobs::charged(conn, job_id, credits).await {
+        Ok(true) => {
+            Metrics::add(&state.metrics.credits_used, credits as u64);
+            state.credits.consume(credits as i64).await;
+        },
+        Ok(false) => info!("[{}] Credits were already recorded!", job_id.0),
+        Err(err) => error!("[{}] Unable to record {} credits used: {}", job_id.0, credits, err)
+    }
+}
+
+/// Whether a job still waits for its webhook. Webhooks of jobs that ended
+/// another way are left alone, except that a replay may retry a job whose
+/// result could not be stored the first time.
+fn is_awaited(job_id: &JobId, backend_job_id: &str, submission: &Submission, replay: bool) -> Result<bool, String> {
+    match submission.status {
+        JobStatus::Processing => Ok(true),
+        JobStatus::Failed if replay && submission.file_id.is_none() => {
+            info!("[{}] Processing failed job again for a replay!", job_id.0);
+            Ok(true)
+        },
+        JobStatus::Cancelled => {
+            info!("[{}] Job was cancelled, ignoring its webhook!", job_id.0);
+            Ok(false)
+        },
+        status => {
+            warn!("[{}] Job is {}, ignoring webhook of {}!", job_id.0, status, backend_job_id);
+            Err(format!("The job is {}, not processing", status))
+        }
+    }
+}
+
+fn json(ok: bool) -> Response {
+    let ok = json!(ok);
+    let response = json!({
+        "ok": ok
+    });
+
+    Json(response).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_awaited;
+    use crate::{converter::jobs::{JobStatus, Submission}, JobId};
+
+    fn submission(status: JobStatus, file_id: Option<i32>) -> Submission {
+        Submission {
+            status,
+            backend_job_id: Some("cc-job".to_string()),
+            file_id,
+            attempts: 0
+        }
+    }
+
+    #[test]
+    fn replays_finished_job_whose_result_was_not_stored() {
+        let job_id = JobId("7".to_string());
+        let failed = submission(JobStatus::Failed, None);
+
+        assert_eq!(is_awaited(&job_id, "cc-job", &failed, true), Ok(true));
+        assert!(is_awaited(&job_id, "cc-job", &failed, false).is_err());
+    }
+
+    #[test]
+    fn leaves_jobs_that_ended_otherwise_alone() {
+        let job_id = JobId("7".to_string());
+
+        assert_eq!(is_awaited(&job_id, "cc-job", &submission(JobStatus::Processing, None), false), Ok(true));
+        assert_eq!(is_awaited(&job_id, "cc-job", &submission(JobStatus::Cancelled, None), true), Ok(false));
+        assert!(is_awaited(&job_id, "cc-job", &submission(JobStatus::Failed, Some(3)), true).is_err());
+        assert!(is_awaited(&job_id, "cc-job", &submission(JobStatus::Finished, Some(3)), true).is_err());
+    }
 }
@@ -0,0 +1,82 @@
+use axum::{extract::State, http::HeaderMap, response::Response};
+use diesel_async::AsyncPgConnection;
+use tracing::{error, info, warn};
+
+use crate::{
+    converter::jobs,
+    database::DatabaseConnection,
+    protocol::ServerMessage,
+    response::Job,
+    webhook::{deliveries::{self, JOB_FAILED}, WebhookEvent},
+    SharedState
+};
+
+/// Shown when CloudConvert gave up on the conversion. What it said is only
+/// logged, it means little to users and would split the failure metrics.
+const CONVERSION_FAILED: &str = "Your file could not be converted!";
+
+pub async fn failed(
+    State(state): State<SharedState>,
+    DatabaseConnection(mut conn): DatabaseConnection,
+    headers: HeaderMap,
+    body: String
+) -> Response {
+    super::receive(&state, &mut conn, WebhookEvent::Failed, &headers, &body).await
+}
+
+/// Records that CloudConvert could not convert a job and tells its session,
+/// which can then try again.
+pub(crate) async fn process(state: &SharedState, conn: &mut AsyncPgConnection, job: Job, replay: bool) -> Result<(), String> {
+    let (job_id, submission) = super::submitted_job(conn, &job).await?;
+    let (backend_job_id, tasks) = (job.id, job.tasks);
+
+    super::charge(state, conn, &job_id, &tasks).await;
+    if !super::is_awaited(&job_id, &backend_job_id, &submission, replay)? {
+        return Ok(())
+    }
+
+    for task in tasks.iter().filter(|task| task.status.as_deref() == Some("error")) {
+        warn!(
+            "[{}] Task {} failed at CloudConvert: {}",
+            job_id.0, task.name, task.message.as_deref().unwrap_or("no reason given")
+        );
+    }
+
+    if let Err(err) = deliveries::processed(conn, &backend_job_id, JOB_FAILED, None).await {
+        error!("[{}] Unable to record processed delivery: {}", job_id.0, err);
+    }
+
+    if let Err(err) = jobs::fail(conn, &job_id, CONVERSION_FAILED).await {
+        error!("[{}] Unable to record the failure of the job: {}", job_id.0, err);
+    }
+
+    let submitted_at = state.pending_jobs.read().await
+        .get(&job_id)
+        .and_then(|pending_job| pending_job.submitted_at);
+
+    if let Some(submitted_at) = submitted_at {
+        state.metrics.job_duration.observe(&[], submitted_at.elapsed().as_secs_f64());
+    }
+
+    state.metrics.failures.increment(&["conversion", CONVERSION_FAILED]);
+
+    // The job no longer counts against the in-flight limit.
+    state.submissions.wake();
+
+    let message = ServerMessage::JobFailed {
+        job_id: job_id.0.clone(),
+        reason: CONVERSION_FAILED.to_string()
+    };
+
+    // Jobs only known to the database since a restart are announced when
+    // their session next connects.
+    if state.publish(&job_id, message).await {
+        if let Err(err) = jobs::mark_notified(conn, &job_id).await {
+            error!("[{}] Unable to record notification: {}", job_id.0, err);
+        }
+    } else {
+        info!("[{}] Client is not connected, the failure will be delivered later!", job_id.0);
+    }
+
+    Ok(())
+}
@@ -1,186 +1,307 @@
-use axum::{extract::State, response::{IntoResponse, Response}, Json};
-use diesel::SelectableHelper;
-use diesel_async::RunQueryDsl;
-use serde_json::{json, Value};
-use tokio::sync::mpsc::Sender;
-use tracing::{debug, error, info, warn};
+use axum::{extract::State, http::HeaderMap, response::Response};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use tracing::{error, info, warn};
 use base64::{Engine, engine::general_purpose::STANDARD};
+use sha2::{Digest, Sha256};
 
 use crate::{
-    database::{models::{File, NewFile}, DatabaseConnection}, response::{
+    converter::{formats, jobs::{self, PendingJob}, retry, text}, database::{models::NewFile, schema::files, DatabaseConnection}, metrics::Metrics,
+    protocol::{JobStage, ServerMessage}, response::{
         Job,
-        JobTask
-    }, JobId, JobStatus, SharedState, SocketMessage
+        JobTask,
+        TaskFile
+    }, scanner::ScanResult, webhook::{deliveries::{self, JOB_FINISHED}, WebhookEvent}, JobId, SharedState
 };
 
 pub async fn finished(
     State(state): State<SharedState>,
     DatabaseConnection(mut conn): DatabaseConnection,
-    Json(body): Json<Value>
+    headers: HeaderMap,
+    body: String
 ) -> Response {
-    {
-        let event = &body["event"];
-        if event != &Value::String(format!("job.finished")) {
-            warn!("Recieved {} event on /webhooks/finished", body["event"]); 
-            return json(false)
-        }
+    super::receive(&state, &mut conn, WebhookEvent::Finished, &headers, &body).await
+}
 
-        info!("Recieved \"job.finished\" event!");
-    }
+/// Downloads, stores and announces the result of a finished job, returning
+/// why the webhook could not be processed if it couldn't.
+pub(crate) async fn process(state: &SharedState, conn: &mut AsyncPgConnection, job: Job, replay: bool) -> Result<(), String> {
+    let (job_id, submission) = super::submitted_job(conn, &job).await?;
+    let (backend_job_id, tasks) = (job.id, job.tasks);
 
-    let job = body["job"].clone();
-    let job = serde_json::from_value::<Job>(job);
-    if let Err(err) = job {
-        warn!("Recieved error when attempting to unwrap job: {}", err);
-        return json(false);
+    super::charge(state, conn, &job_id, &tasks).await;
+    if !super::is_awaited(&job_id, &backend_job_id, &submission, replay)? {
+        return Ok(())
     }
 
-    let job = job.unwrap();
-    let (id, tasks) = (job.id, job.tasks);
+    let pending_job = state.pending_jobs.read().await
+        .get(&job_id)
+        .cloned();
 
-    info!("[Job {}] Recieved completion response!", id);
-    let job_id = JobId(id);
+    // Jobs started before a restart are only known to the database.
+    let pending_job = match pending_job {
+        Some(pending_job) => Some(pending_job),
+        None => match jobs::restore(conn, &job_id).await {
+            Ok(Some(pending_job)) => {
+                info!("[{}] Restored job from the database!", job_id.0);
+                state.pending_jobs.write().await.insert(job_id.clone(), pending_job.clone());
+                Some(pending_job)
+            },
+            Ok(None) => None,
+            Err(err) => {
+                error!("[{}] Unable to restore job: {}", job_id.0, err);
+                None
+            }
+        }
+    };
 
-    let pending_jobs = &state.pending_jobs.read().await;
-    if !pending_jobs.contains_key(&job_id) {
+    if pending_job.is_none() {
         warn!("[{}] Job has no assigned session!", job_id.0);
-        return json(false)
+        return Err("The job has no assigned session".to_string())
     }
 
-    let session_id = pending_jobs.get(&job_id);
-    if session_id.is_none() {
-        warn!("[{}] Job is not in pending_jobs!", job_id.0);
-        return json(false)
-    }
+    let pending_job = pending_job.unwrap();
 
-    let session_id = session_id.unwrap().to_string();
+    let preview_url = find_task(&tasks, "export-my-thumbnail")
+        .and_then(|task| task.result.as_ref())
+        .and_then(|result| result.files[..].first())
+        .and_then(|file| file.url.clone());
 
-    let task = find_export_task(tasks);
+    let task = find_task(&tasks, "export-my-file");
     if task.is_none() {
         warn!("[Job {}] Webhook does not contain export-my-file task!", job_id.0);
-        return json(false)
+        return Err("The webhook does not contain the export-my-file task".to_string())
     }
 
     let task = task.unwrap();
-    let file = task.result.files.get(0);
-    let mut clients = state.connected_clients.write().await; 
-    let client = clients.get(&session_id);
-    if client.is_none() {
-        warn!("[{}] Client is no longer connected!", job_id.0);
-        return json(false)
+    if task.result.is_none() {
+        warn!("[Job {}] export-my-file task has no result!", job_id.0);
+        return Err("The export-my-file task has no result".to_string())
     }
 
-    let client = client.unwrap();
-
-    let success = match file {
-        Some(file) => {
-            let url = file.url.clone();
-            match url {
-                Some(url) => {
-                    let reqwest_client = reqwest::Client::new();
-                    let response = reqwest_client.get(url)
-                        .send()
-                        .await
-                        .unwrap();
-
-                    match response.error_for_status() {
-                        Ok(response) => {
-                            let bytes = response.bytes().await;
-                            match bytes {
-                                Ok(bytes) => {
-                                    let base64 = STANDARD.encode(bytes);
-                                    let new_file = NewFile {
-                                        file_name: &file.file_name,
-                                        content: &base64
-                                    };
-
-                                    let file = diesel::insert_into(crate::database::schema::files::table)
-                                        .values(&new_file)
-                                        .returning(File::as_returning())
-                                        .get_result(&mut conn)
-                                        .await;
-
-                                    match file {
-                                        Ok(file) => {
-                                            send_client_message(client, SocketMessage {
-                                                job_id: job_id.clone(),
-                                                job_status: JobStatus::COMPLETED,
-                                                file_id: Some(file.id)
-                                            }).await;
-                                            
-                                            true
-                                        },
-                                        Err(_) => {
-                                            error!("[{}] There was an error while attempting to upload the file to the database!", job_id.0);
-                                            false
-                                        }
-                                    }
-
-                                },
-                                Err(err) => {
-                                    error!("[{}] Recieved error code when attempting to request file: {}", job_id.0, err);
-                                    false
-                                }
-                            }
-                        },
-                        Err(_) => {
-                            error!("[{}] Could not get converted file from given URL.", job_id.0);
-                            false
-                        }
-                    }
-                },
-                None => {
-                    error!("[{}] Could not find any specified URL from the task!", job_id.0);
-                    false
-                }
-            }
+    let task_result = task.result.as_ref().unwrap();
+    let file = task_result.files[..].first();
+    let stored = match (existing_file(conn, &backend_job_id).await, file) {
+        (Some(file_id), _) => {
+            info!("[{}] Result was already stored as file {}!", job_id.0, file_id);
+            Ok(file_id)
         },
-        None => {
+        (None, Some(file)) => store_result(state, conn, &job_id, &backend_job_id, &pending_job, file, preview_url).await,
+        (None, None) => {
             error!("[{}] Could not find any file in task!", job_id.0);
-            false
+            Err(StoreError::Failed("The converter did not return a file!"))
         }
     };
 
-    if success == false {
-        send_client_message(client, SocketMessage {
-            job_id,
-            job_status: JobStatus::FAILED,
-            file_id: None
-        }).await;
+    // The job keeps waiting and its delivery stays unprocessed, so replaying
+    // the webhook can store the result once that is possible again.
+    let stored = match stored {
+        Ok(file_id) => Ok(file_id),
+        Err(StoreError::Failed(reason)) => Err(reason),
+        Err(StoreError::Retry(reason)) => {
+            warn!("[{}] Result can't be stored right now: {}", job_id.0, reason);
+            return Err(reason)
+        }
+    };
+
+    if let Err(err) = deliveries::processed(conn, &backend_job_id, JOB_FINISHED, stored.ok()).await {
+        error!("[{}] Unable to record processed delivery: {}", job_id.0, err);
     }
 
-    clients.remove(&session_id);
-    drop(clients);
+    let recorded = match &stored {
+        Ok(file_id) => jobs::finish(conn, &job_id, *file_id).await,
+        Err(reason) => jobs::fail(conn, &job_id, reason).await
+    };
 
-    json(success)
-}
+    if let Err(err) = recorded {
+        error!("[{}] Unable to record the outcome of the job: {}", job_id.0, err);
+    }
+
+    if let Some(submitted_at) = pending_job.submitted_at {
+        state.metrics.job_duration.observe(&[], submitted_at.elapsed().as_secs_f64());
+    }
+
+    if let Err(reason) = stored {
+        state.metrics.failures.increment(&["result", reason]);
+    }
+
+    // The job no longer counts against the in-flight limit.
+    state.submissions.wake();
+
+    let message = match stored {
+        Ok(file_id) => ServerMessage::JobCompleted {
+            job_id: job_id.0.clone(),
+            file_id
+        },
+        Err(reason) => ServerMessage::JobFailed {
+            job_id: job_id.0.clone(),
+            reason: reason.to_string()
+        }
+    };
 
-async fn send_client_message(client: &Sender<SocketMessage>, msg: SocketMessage) -> bool {
-    let job_id = msg.job_id.clone();
-    if let Err(err) = client.send(msg).await {
-        error!("[Job {}] Recieved error while attempting to send message to client.", job_id.0);
-        debug!("[Job {}] Error: {}", job_id.0, err);
-        return false
+    // Whoever isn't connected now is told the next time they connect or
+    // open their history.
+    if state.publish(&job_id, message).await {
+        if let Err(err) = jobs::mark_notified(conn, &job_id).await {
+            error!("[{}] Unable to record notification: {}", job_id.0, err);
+        }
+    } else {
+        info!("[{}] Client is not connected, the result will be delivered later!", job_id.0);
     }
 
-    return true
+    stored.map(|_| ()).map_err(str::to_string)
 }
 
-fn json(ok: bool) -> Response {
-    let ok = json!(ok);
-    let response = json!({
-        "ok": ok
-    });
+/// Why a result was not stored.
+enum StoreError {
+    /// The job failed, with the reason shown to the user.
+    Failed(&'static str),
+    /// Storing may work later, e.g. once the virus scanner is back.
+    Retry(String)
+}
 
-    return Json(response).into_response()
-} 
+/// Downloads, scans and stores the converted file, returning its id or why
+/// it was not stored.
+async fn store_result(
+    state: &SharedState,
+    conn: &mut AsyncPgConnection,
+    job_id: &JobId,
+    backend_job_id: &str,
+    pending_job: &PendingJob,
+    file: &TaskFile,
+    preview_url: Option<String>
+) -> Result<i32, StoreError> {
+    let url = match &file.url {
+        Some(url) => url.clone(),
+        None => {
+            error!("[{}] Could not find any specified URL from the task!", job_id.0);
+            return Err(StoreError::Failed("The converter did not return a file!"))
+        }
+    };
+
+    state.publish(job_id, ServerMessage::JobProgress {
+        job_id: job_id.0.clone(),
+        stage: JobStage::Downloading
+    }).await;
+
+    let download = reqwest::Client::new().get(url);
+    let response = retry::send(&state.metrics, "download", download, &format!("[{}] Download", job_id.0))
+        .await
+        .and_then(|response| response.error_for_status());
+    let bytes = match response {
+        Ok(response) => match response.bytes().await {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                error!("[{}] Recieved error code when attempting to request file: {}", job_id.0, err);
+                return Err(StoreError::Failed("Your converted file could not be downloaded!"))
+            }
+        },
+        Err(_) => {
+            error!("[{}] Could not get converted file from given URL.", job_id.0);
+            return Err(StoreError::Failed("Your converted file could not be downloaded!"))
+        }
+    };
 
-fn find_export_task(tasks: Vec<JobTask>) -> Option<JobTask> {
-    for task in tasks {
-        if task.operation == "export/url" {
-            return Some(task) 
+    match state.scanner.scan(&bytes).await {
+        Ok(ScanResult::Clean) => {},
+        Ok(ScanResult::Infected(signature)) => {
+            warn!("[{}] Discarded converted file, virus scan found {}", job_id.0, signature);
+            return Err(StoreError::Failed("Your converted file was flagged by our virus scanner!"))
+        },
+        Err(err) => {
+            // Nothing unscanned is handed out, the result waits for a replay.
+            error!("[{}] Unable to scan converted file: {}", job_id.0, err);
+            return Err(StoreError::Retry(format!("The virus scanner is unavailable: {}", err)))
         }
     }
 
-    return None
+    state.publish(job_id, ServerMessage::JobProgress {
+        job_id: job_id.0.clone(),
+        stage: JobStage::Storing
+    }).await;
+
+    let base64 = STANDARD.encode(&bytes);
+    let sha256 = format!("{:x}", Sha256::digest(&bytes));
+    let source_format = formats::extension(&pending_job.source_file_name);
+    let content_text = text::extract(pending_job.target_format.clone(), bytes.to_vec())
+        .await
+        .or(pending_job.source_text.clone());
+    let preview = fetch_preview(&state.metrics, job_id, preview_url).await;
+    let new_file = NewFile {
+        file_name: &file.file_name,
+        content: &base64,
+        size_bytes: bytes.len() as i64,
+        mime_type: formats::mime_type(&pending_job.target_format),
+        source_file_name: Some(&pending_job.source_file_name),
+        source_format: Some(&source_format),
+        target_format: &pending_job.target_format,
+        backend_job_id: Some(backend_job_id),
+        sha256: &sha256,
+        cache_key: Some(&pending_job.cache_key),
+        content_text: content_text.as_deref(),
+        preview: preview.as_deref()
+    };
+
+    let file = diesel::insert_into(crate::database::schema::files::table)
+        .values(&new_file)
+        .returning(files::id)
+        .get_result::<i32>(conn)
+        .await;
+
+    match file {
+        Ok(file_id) => Ok(file_id),
+        Err(_) => {
+            error!("[{}] There was an error while attempting to upload the file to the database!", job_id.0);
+            Err(StoreError::Failed("Your converted file could not be saved!"))
+        }
+    }
+}
+
+/// The file already stored for the backend job, if any.
+async fn existing_file(conn: &mut AsyncPgConnection, backend_job_id: &str) -> Option<i32> {
+    let file_id = files::table
+        .filter(files::backend_job_id.eq(backend_job_id))
+        .select(files::id)
+        .first::<i32>(conn)
+        .await
+        .optional();
+
+    match file_id {
+        Ok(file_id) => file_id,
+        Err(err) => {
+            error!("[Job {}] Unable to look up stored result: {}", backend_job_id, err);
+            None
+        }
+    }
+}
+
+fn find_task<'a>(tasks: &'a [JobTask], name: &str) -> Option<&'a JobTask> {
+    tasks.iter().find(|task| task.name == name && task.operation == "export/url")
+}
+
+/// Downloads the PNG thumbnail of a job as base64. A missing or broken
+/// preview is logged but never fails the job.
+async fn fetch_preview(metrics: &Metrics, job_id: &JobId, url: Option<String>) -> Option<String> {
+    let url = url?;
+    let download = reqwest::Client::new().get(url);
+    let response = retry::send(metrics, "preview", download, &format!("[{}] Preview download", job_id.0))
+        .await
+        .and_then(|response| response.error_for_status());
+    match response {
+        Ok(response) => match response.bytes().await {
+            Ok(bytes) if bytes.starts_with(b"\x89PNG") => Some(STANDARD.encode(bytes)),
+            Ok(_) => {
+                warn!("[{}] Preview is not a PNG image, skipping it!", job_id.0);
+                None
+            },
+            Err(err) => {
+                warn!("[{}] Unable to read preview: {}", job_id.0, err);
+                None
+            }
+        },
+        Err(err) => {
+            warn!("[{}] Unable to download preview: {}", job_id.0, err);
+            None
+        }
+    }
 }
@@ -1,29 +1,74 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, time::Duration};
 use async_recursion::async_recursion;
-use tokio::sync::mpsc;
+use tokio::{sync::broadcast::error::RecvError, time};
 
-use axum::{extract::{ws::{Message, WebSocket}, ConnectInfo, State, WebSocketUpgrade}, response::IntoResponse};
-use tracing::{info, warn, error};
-use futures::{sink::SinkExt, stream::{SplitStream, StreamExt}};
+use axum::{
+    extract::{ws::{Message, WebSocket}, ConnectInfo, State, WebSocketUpgrade},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response}
+};
+use tower_sessions::Session;
+use tracing::{debug, info, warn, error};
+use futures::{sink::SinkExt, stream::{SplitSink, SplitStream, StreamExt}};
 
-use crate::{JobStatus, SharedState, SocketMessage};
+use crate::{
+    protocol::{ClientEnvelope, ClientMessage, ServerMessage, PROTOCOL_VERSION},
+    SharedState
+};
+
+/// How often an idle socket is sent a heartbeat.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
 
 pub(super) async fn socket(
     State(state): State<SharedState>,
+    session: Session,
+    headers: HeaderMap,
     ws: WebSocketUpgrade,
     ConnectInfo(addr): ConnectInfo<SocketAddr>    
-) -> impl IntoResponse {
+) -> Response {
     info!("[{}] Recieved socket connection!", addr);
+
+    // Browsers send cookies with cross-site socket connections too, so only
+    // accept pages served by us.
+    if !is_same_origin(&headers) {
+        warn!("[{}] Rejected socket from foreign origin {:?}!", addr, headers.get(header::ORIGIN));
+        return StatusCode::FORBIDDEN.into_response()
+    }
+
+    // The socket belongs to whichever session the cookie proves, never to an
+    // id the client claims.
+    let session_id = match session.id() {
+        Some(id) => id.to_string(),
+        None => {
+            warn!("[{}] Rejected socket without a session cookie!", addr);
+            return StatusCode::UNAUTHORIZED.into_response()
+        }
+    };
+
     ws.on_upgrade(move |socket| {
-        handle_socket(state, socket, addr)
+        handle_socket(state, socket, addr, session_id)
     })
 }
 
+fn is_same_origin(headers: &HeaderMap) -> bool {
+    let origin = headers.get(header::ORIGIN).and_then(|origin| origin.to_str().ok());
+    let host = headers.get(header::HOST).and_then(|host| host.to_str().ok());
+
+    match (origin, host) {
+        // Non-browser clients don't send an origin.
+        (None, _) => true,
+        (Some(origin), Some(host)) => origin.split_once("://").is_some_and(|(_, origin_host)| origin_host == host),
+        (Some(_), None) => false
+    }
+}
+
 async fn handle_socket(
     state: SharedState,
     stream: WebSocket,
-    addr: SocketAddr
+    addr: SocketAddr,
+    session_id: String
 ) {
+    let _connection = state.metrics.track_websocket();
     let (mut sender, mut reciever) = stream.split();
 
     if sender.send(Message::Ping(vec![1, 2, 3])).await.is_err() {
@@ -31,43 +76,66 @@ async fn handle_socket(
         return;
     }
 
-    let session_id = find_socket_id(&mut reciever).await;
-    if session_id.is_none() {
-        warn!("[{}] No session_id!", addr);
+    let hello = find_hello(&mut reciever).await;
+    if hello.is_none() {
+        warn!("[{}] Client did not say hello!", addr);
+        let _ = send(&mut sender, &ServerMessage::Error { reason: "expected-hello".to_string() }).await;
         return;
     }
 
-    let session_id = session_id.unwrap();
-    info!("[{}] Client connected with id: {}!", addr, session_id);
+    let (version, claimed_session_id) = hello.unwrap();
+    if version != PROTOCOL_VERSION {
+        warn!("[{}] Client speaks protocol version {}, expected {}!", addr, version, PROTOCOL_VERSION);
+        let _ = send(&mut sender, &ServerMessage::Error { reason: "unsupported-version".to_string() }).await;
+        return;
+    }
 
-    let (tx, mut rx) = mpsc::channel::<SocketMessage>(10);
-    let mut clients = state.connected_clients.write().await;
-    if clients.contains_key(&session_id) {
-        info!("[{}] Client already connected with Session id {}!", addr, session_id);
-        let _ = sender.send(Message::Text("duplicate-connection".to_string())).await;
+    if claimed_session_id.is_some_and(|claimed| claimed != session_id) {
+        warn!("[{}] Client claimed a session other than its cookie's!", addr);
+        let _ = send(&mut sender, &ServerMessage::Error { reason: "session-mismatch".to_string() }).await;
         return;
     }
 
-    clients.insert(session_id.clone(), tx);
-    drop(clients);
+    info!("[{}] Client connected with id: {}!", addr, session_id);
+
+    let mut rx = state.subscribe(&session_id).await;
+
+    if send(&mut sender, &ServerMessage::Hello).await.is_err() {
+        error!("[{}] Unable to greet client!", addr);
+    }
+
+    let mut replay = state.replay(&session_id, None, None).await;
+    replay.extend(state.undelivered(&session_id).await);
+    for event in replay {
+        if send(&mut sender, &event.message).await.is_err() {
+            error!("[{}] Unable to replay {:?}!", addr, event.message);
+            break;
+        }
+
+        info!("[{}] Replayed {:?}", addr, event.message);
+    }
 
     let mut rx_task = tokio::spawn(async move {
-        while let Some(msg) = rx.recv().await {
-            let status = msg.job_status;
-            let message = match status {
-                JobStatus::PENDING => continue,
-                JobStatus::FAILED => {
-                    format!("job-failed;{}", msg.job_id.0)
+        let mut heartbeat = time::interval(HEARTBEAT_INTERVAL);
+        heartbeat.tick().await;
+
+        loop {
+            let msg = tokio::select! {
+                msg = rx.recv() => match msg {
+                    Ok(event) => event.message,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("[{}] Socket fell behind and missed {} messages!", addr, skipped);
+                        continue
+                    },
+                    Err(RecvError::Closed) => break
                 },
-                JobStatus::COMPLETED => {
-                    format!("job-completed;{}", msg.file_id.unwrap())
-                }
+                _ = heartbeat.tick() => ServerMessage::Heartbeat
             };
 
-            if sender.send(Message::Text(message)).await.is_err() {
+            if send(&mut sender, &msg).await.is_err() {
                 error!("[{}] There was an error while trying to send converter response!", addr); 
             } else {
-                info!("[{}] Sent response with converted response!", addr);
+                info!("[{}] Sent {:?}!", addr, msg);
             }
         }
     });
@@ -76,39 +144,57 @@ async fn handle_socket(
         while let Some(Ok(msg)) = reciever.next().await {
             let data = extract_message_data(msg);
             match data {
+                Either::Left(text) => {
+                    match ClientEnvelope::parse(&text).map(|envelope| envelope.message) {
+                        Some(ClientMessage::Heartbeat {}) => debug!("[{}] Recieved heartbeat!", addr),
+                        Some(message) => debug!("[{}] Ignoring unexpected message {:?}", addr, message),
+                        None => warn!("[{}] Recieved malformed message: {}", addr, text)
+                    }
+                },
                 Either::Right(should_close) => {
                     let should_close = should_close.into();
                     if should_close {
                         break
                     }
-                },
-                _ => continue
+                }
             }
         }
     });
 
     tokio::select! {
         _ = &mut rx_task => socket_task.abort(),
-        _ = &mut socket_task => rx_task.abort()
+        _ = &mut socket_task => {
+            rx_task.abort();
+            // Wait for the aborted task to drop its receiver before checking
+            // whether this was the session's last socket.
+            let _ = rx_task.await;
+        }
     };
 
-    let mut clients = state.connected_clients.write().await;
-    clients.remove(&session_id);
-    drop(clients);
+    state.unsubscribe(&session_id).await;
 
     info!("[{}] Socket with ID {} disconnected!", addr, session_id);
 }
 
+async fn send(sender: &mut SplitSink<WebSocket, Message>, message: &ServerMessage) -> Result<(), axum::Error> {
+    sender.send(Message::Text(message.to_json())).await
+}
+
+/// Waits for the client's hello, returning its protocol version and the
+/// session id it claims, if any.
 #[async_recursion]
-async fn find_socket_id(reciever: &mut SplitStream<WebSocket>) -> Option<String> {
+async fn find_hello(reciever: &mut SplitStream<WebSocket>) -> Option<(u32, Option<String>)> {
     if let Some(Ok(msg)) = reciever.next().await {
         let data = extract_message_data(msg);
         match data {
             Either::Left(message) => {
-                let socket_id = message.split(';').last().unwrap();
-                return Some(socket_id.into())
+                match ClientEnvelope::parse(&message) {
+                    Some(ClientEnvelope { v, message: ClientMessage::Hello { session_id } }) => Some((v, session_id)),
+                    _ => None
+                }
             },
-            _ => find_socket_id(reciever).await
+            Either::Right(ShouldSocketClose(true)) => None,
+            _ => find_hello(reciever).await
         }
     } else {
         None
@@ -117,17 +203,17 @@ async fn find_socket_id(reciever: &mut SplitStream<WebSocket>) -> Option<String>
 
 fn extract_message_data(msg: Message) -> Either<String, ShouldSocketClose> {
     match msg {
-        Message::Text(t) => return Either::Left(t),
-        Message::Close(_) => return Either::Right(true.into()),
-        _ => return Either::Right(false.into())
+        Message::Text(t) => Either::Left(t),
+        Message::Close(_) => Either::Right(true.into()),
+        _ => Either::Right(false.into())
     }
 }
 
 struct ShouldSocketClose(bool);
-impl Into<bool> for ShouldSocketClose {
+impl From<ShouldSocketClose> for bool {
     
-    fn into(self) -> bool {
-        return self.0
+    fn from(value: ShouldSocketClose) -> Self {
+        value.0
     }
 
 }
@@ -135,7 +221,7 @@ impl Into<bool> for ShouldSocketClose {
 impl From<bool> for ShouldSocketClose {
    
     fn from(value: bool) -> Self {
-        return Self(value)
+        Self(value)
     }
 
 }
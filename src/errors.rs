@@ -6,6 +6,13 @@ pub fn internal_error(err: ConverterError) -> (StatusCode, String) {
             ConverterError::Download(message) => (StatusCode::INTERNAL_SERVER_ERROR, message.into()),         
             ConverterError::MissingDependencies(message) => (StatusCode::FAILED_DEPENDENCY, message.into()),
             ConverterError::DatabaseConnection(message) => (StatusCode::INTERNAL_SERVER_ERROR, message.into()),
+            ConverterError::UnsupportedMediaType(message) => (StatusCode::UNSUPPORTED_MEDIA_TYPE, message.into()),
+            ConverterError::PayloadTooLarge(message) => (StatusCode::PAYLOAD_TOO_LARGE, message.into()),
+            ConverterError::Rejected(message) => (StatusCode::UNPROCESSABLE_ENTITY, message.into()),
+            ConverterError::ServiceUnavailable(message) => (StatusCode::SERVICE_UNAVAILABLE, message.into()),
+            ConverterError::NotFound(message) => (StatusCode::NOT_FOUND, message.into()),
+            ConverterError::Conflict(message) => (StatusCode::CONFLICT, message.into()),
+            ConverterError::BadRequest(message) => (StatusCode::BAD_REQUEST, message.into()),
         }
 }
 
@@ -24,4 +31,25 @@ pub enum ConverterError<'de> {
 
     /// Internal Server Error, code 500
     DatabaseConnection(&'de str),
+
+    /// Unsupported Media Type Error, code 415
+    UnsupportedMediaType(&'de str),
+
+    /// Payload Too Large Error, code 413
+    PayloadTooLarge(&'de str),
+
+    /// Unprocessable Entity Error, code 422
+    Rejected(&'de str),
+
+    /// Service Unavailable Error, code 503
+    ServiceUnavailable(&'de str),
+
+    /// Not Found Error, code 404
+    NotFound(&'de str),
+
+    /// Conflict Error, code 409
+    Conflict(&'de str),
+
+    /// Bad Request Error, code 400
+    BadRequest(&'de str),
 }
@@ -5,28 +5,50 @@ pub mod converter;
 pub mod database;
 pub mod webhook;
 pub mod errors;
+pub mod metrics;
+pub mod protocol;
+pub mod scanner;
 
-use converter::jobs::JobId;
+use converter::jobs::{JobId, PendingJob};
 use database::Pool;
-use tokio::sync::{mpsc, RwLock};
+use metrics::Metrics;
+use protocol::{Event, ServerMessage};
+use scanner::Scanner;
+use tokio::sync::{broadcast, RwLock};
+use tracing::{debug, error};
 
-use std::{collections::HashMap, sync::Arc};
+/// How many events a slow socket may fall behind before it starts missing them.
+const CLIENT_CHANNEL_CAPACITY: usize = 32;
+
+use std::{collections::HashMap, sync::{atomic::{AtomicU64, Ordering}, Arc}};
 use diesel_async::{pooled_connection::AsyncDieselConnectionManager, AsyncPgConnection};
 
-pub enum JobStatus {
-    PENDING, FAILED, COMPLETED
+/// A session with at least one socket or event stream open.
+pub struct ConnectedSession {
+    pub session_id: String,
+    pub connections: usize,
+    /// Jobs of the session that haven't ended yet.
+    pub pending_jobs: usize
 }
 
-pub struct SocketMessage {
-    job_status: JobStatus,
-    file_id: Option<i32>,
-    job_id: JobId
+impl ConnectedSession {
+
+    pub fn display_id(&self) -> String {
+        database::models::display_session(&self.session_id)
+    }
+
 }
 
 pub struct State {
     pool: Pool,
-    pending_jobs: RwLock<HashMap<JobId, String>>,
-    connected_clients: RwLock<HashMap<String, mpsc::Sender<SocketMessage>>>
+    pending_jobs: RwLock<HashMap<JobId, PendingJob>>,
+    connected_clients: RwLock<HashMap<String, broadcast::Sender<Event>>>,
+    next_event_id: AtomicU64,
+    webhooks: webhook::Queue,
+    submissions: converter::queue::SubmissionQueue,
+    credits: converter::credits::Credits,
+    scanner: Scanner,
+    metrics: Metrics
 }
 
 impl State {
@@ -34,8 +56,178 @@ impl State {
         State {
             pool: bb8::Pool::builder().build(config).await.unwrap(),
             pending_jobs: RwLock::new(HashMap::new()),
-            connected_clients: RwLock::new(HashMap::new())
+            connected_clients: RwLock::new(HashMap::new()),
+            // Start from the clock so ids a client saw before a restart are
+            // still older than the ones it gets after.
+            next_event_id: AtomicU64::new(chrono::Utc::now().timestamp_millis() as u64),
+            webhooks: webhook::Queue::from_env(),
+            submissions: converter::queue::SubmissionQueue::from_env(),
+            credits: converter::credits::Credits::from_env(),
+            scanner: Scanner::from_env(),
+            metrics: Metrics::default()
+        }
+    }
+
+    /// Registers another socket for `session_id`. Every socket of a session
+    /// receives every message sent to it.
+    pub(crate) async fn subscribe(&self, session_id: &str) -> broadcast::Receiver<Event> {
+        let mut clients = self.connected_clients.write().await;
+        match clients.get(session_id) {
+            Some(client) => client.subscribe(),
+            None => {
+                let (tx, rx) = broadcast::channel(CLIENT_CHANNEL_CAPACITY);
+                clients.insert(session_id.to_string(), tx);
+                rx
+            }
+        }
+    }
+
+    /// Forgets the session once the receiver of its last socket is dropped.
+    pub(crate) async fn unsubscribe(&self, session_id: &str) {
+        let mut clients = self.connected_clients.write().await;
+        if clients.get(session_id).is_some_and(|client| client.receiver_count() == 0) {
+            clients.remove(session_id);
+        }
+    }
+
+    /// Moves the jobs and sockets of the session `from` to its new id `to`,
+    /// e.g. after the id was changed on login.
+    pub(crate) async fn move_session(&self, from: &str, to: &str) {
+        match self.pool.get().await {
+            Ok(mut conn) => match converter::jobs::move_session(&mut conn, from, to).await {
+                Ok(moved) => debug!("[{}] Moved {} jobs to the new session id!", to, moved),
+                Err(err) => error!("[{}] Unable to move jobs to the new session id: {}", to, err)
+            },
+            Err(err) => error!("[{}] Unable to connect to database to move jobs: {}", to, err)
+        }
+
+        for job in self.pending_jobs.write().await.values_mut().filter(|job| job.session_id == from) {
+            job.session_id = to.to_string();
+        }
+
+        let mut clients = self.connected_clients.write().await;
+        if let Some(client) = clients.remove(from) {
+            clients.entry(to.to_string()).or_insert(client);
+        }
+    }
+
+    /// Hands `message` to every socket of `session_id`, returning whether any
+    /// was connected to receive it.
+    pub(crate) async fn notify(&self, session_id: &str, message: ServerMessage) -> bool {
+        let event = self.next_event(message);
+        self.deliver(session_id, event).await
+    }
+
+    async fn deliver(&self, session_id: &str, event: Event) -> bool {
+        let clients = self.connected_clients.read().await;
+        let delivered = clients.get(session_id)
+            .is_some_and(|client| client.send(event.clone()).is_ok());
+
+        if !delivered {
+            debug!("[{}] No socket connected to receive {:?}", session_id, event.message);
+        }
+
+        delivered
+    }
+
+    pub(crate) fn next_event(&self, message: ServerMessage) -> Event {
+        Event {
+            id: self.next_event_id.fetch_add(1, Ordering::Relaxed),
+            message
+        }
+    }
+
+    /// Records `message` in the history of `job_id`, so it can be replayed
+    /// when the session reconnects, and sends it to the session. Jobs are
+    /// forgotten after their final message, which is replayed from `jobs`
+    /// if nobody was connected to receive it.
+    pub(crate) async fn publish(&self, job_id: &JobId, message: ServerMessage) -> bool {
+        let terminal = message.is_terminal();
+        let (session_id, event) = {
+            let mut pending_jobs = self.pending_jobs.write().await;
+            match pending_jobs.get_mut(job_id) {
+                Some(job) => {
+                    let event = self.next_event(message);
+                    job.events.push(event.clone());
+                    (job.session_id.clone(), event)
+                },
+                None => return false
+            }
+        };
+
+        let delivered = self.deliver(&session_id, event).await;
+        if terminal {
+            self.pending_jobs.write().await.remove(job_id);
+        }
+
+        delivered
+    }
+
+    /// Every session currently connected, most connections first.
+    pub(crate) async fn connected_sessions(&self) -> Vec<ConnectedSession> {
+        let clients = self.connected_clients.read().await;
+        let pending_jobs = self.pending_jobs.read().await;
+
+        let mut sessions: Vec<ConnectedSession> = clients.iter()
+            .map(|(session_id, client)| ConnectedSession {
+                session_id: session_id.clone(),
+                connections: client.receiver_count(),
+                pending_jobs: pending_jobs.values().filter(|job| &job.session_id == session_id).count()
+            })
+            .collect();
+
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.connections));
+        sessions
+    }
+
+    /// Final messages of the session's jobs that ended while none of its
+    /// clients were connected. They count as delivered once returned.
+    pub(crate) async fn undelivered(&self, session_id: &str) -> Vec<Event> {
+        let finished = match self.pool.get().await {
+            Ok(mut conn) => converter::jobs::take_undelivered(&mut conn, session_id).await,
+            Err(err) => {
+                error!("[{}] Unable to connect to database for undelivered jobs: {}", session_id, err);
+                return Vec::new()
+            }
+        };
+
+        match finished {
+            Ok(finished) => finished.iter()
+                .filter_map(converter::jobs::final_message)
+                .map(|message| self.next_event(message))
+                .collect(),
+            Err(err) => {
+                error!("[{}] Unable to look up undelivered jobs: {}", session_id, err);
+                Vec::new()
+            }
+        }
+    }
+
+    /// The events of the session's pending jobs a client that last saw
+    /// `after` has missed. Jobs whose final event is handed out are forgotten.
+    pub(crate) async fn replay(&self, session_id: &str, job_id: Option<&JobId>, after: Option<u64>) -> Vec<Event> {
+        let mut pending_jobs = self.pending_jobs.write().await;
+        let mut events = Vec::new();
+        let mut finished = Vec::new();
+        for (id, job) in pending_jobs.iter() {
+            if job.session_id != session_id || job_id.is_some_and(|job_id| job_id != id) {
+                continue
+            }
+
+            let missed = job.events_after(after);
+            if missed.last().is_some_and(|event| event.message.is_terminal()) {
+                finished.push(id.clone());
+            }
+
+            events.extend(missed);
         }
+
+        for id in finished {
+            pending_jobs.remove(&id);
+        }
+
+        events.sort_by_key(|event| event.id);
+        events
     }
 
 }
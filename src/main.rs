@@ -12,7 +12,7 @@ use diesel_async::{
     pooled_connection::AsyncDieselConnectionManager, AsyncPgConnection
 };
 use file_converter::{
-    endpoints::get_router, SharedState, State
+    converter::{credits, queue}, database::migrations::run_pending_migrations, endpoints::get_router, webhook, SharedState, State
 };
 
 #[tokio::main]
@@ -31,11 +31,23 @@ async fn main() -> Result<()> {
     let db_url = env::var("DATABASE_URL")
         .expect("DATABASE_URL must be set! Check your .env file!");
 
+    // `file_converter migrate` applies pending migrations and exits, while a
+    // normal start applies them before accepting any traffic.
+    let migrate_only = env::args().nth(1).is_some_and(|arg| arg == "migrate");
+    run_pending_migrations(db_url.clone()).await?;
+    if migrate_only {
+        return Ok(())
+    }
+
     let config = AsyncDieselConnectionManager::<AsyncPgConnection>::new(db_url);
     let shared_state: SharedState = Arc::new(
             State::default(config).await
     );
 
+    webhook::start_workers(shared_state.clone()).await;
+    queue::start(shared_state.clone()).await;
+    credits::start_monitor(shared_state.clone()).await;
+
     let redis_url = env::var("REDIS_URL")
         .expect("REDIS_URL must be set! Check your .env file!");
 
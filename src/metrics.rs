@@ -0,0 +1,293 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{atomic::{AtomicI64, AtomicU64, Ordering}, Mutex}
+};
+
+use crate::State;
+
+/// Prefix of every exported metric.
+const NAMESPACE: &str = "file_converter";
+
+/// Upper bounds of the job duration buckets, in seconds.
+const JOB_DURATION_BUCKETS: &[f64] = &[1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0];
+
+/// Upper bounds of the backend latency buckets, in seconds.
+const LATENCY_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// Process-wide counters, shared through [`crate::State`].
+pub struct Metrics {
+    pub cache_hits: AtomicU64,
+    pub cache_misses: AtomicU64,
+    /// Credits left at CloudConvert as of the last check.
+    pub credits_remaining: AtomicI64,
+    /// Credits charged for jobs since the server started.
+    pub credits_used: AtomicU64,
+    /// Uploads accepted for conversion, by source and target format.
+    pub conversions: CounterVec,
+    /// Jobs that failed, by the stage they failed in and the reason shown.
+    pub failures: CounterVec,
+    /// Seconds from handing a job to CloudConvert until its webhook was
+    /// processed.
+    pub job_duration: Histogram,
+    /// Seconds CloudConvert took to answer a request, by operation.
+    pub backend_latency: Histogram,
+    pub upload_bytes: AtomicU64,
+    pub download_bytes: AtomicU64,
+    pub websocket_connections: AtomicI64
+}
+
+impl Default for Metrics {
+
+    fn default() -> Self {
+        Metrics {
+            cache_hits: AtomicU64::default(),
+            cache_misses: AtomicU64::default(),
+            credits_remaining: AtomicI64::default(),
+            credits_used: AtomicU64::default(),
+            conversions: CounterVec::default(),
+            failures: CounterVec::default(),
+            job_duration: Histogram::new(JOB_DURATION_BUCKETS),
+            backend_latency: Histogram::new(LATENCY_BUCKETS),
+            upload_bytes: AtomicU64::default(),
+            download_bytes: AtomicU64::default(),
+            websocket_connections: AtomicI64::default()
+        }
+    }
+
+}
+
+impl Metrics {
+
+    /// Increments `counter` and returns its new value.
+    pub fn increment(counter: &AtomicU64) -> u64 {
+        Metrics::add(counter, 1)
+    }
+
+    /// Adds `amount` to `counter` and returns its new value.
+    pub fn add(counter: &AtomicU64, amount: u64) -> u64 {
+        counter.fetch_add(amount, Ordering::Relaxed) + amount
+    }
+
+    pub fn set(gauge: &AtomicI64, value: i64) {
+        gauge.store(value, Ordering::Relaxed);
+    }
+
+    /// Counts a websocket connection until the returned guard is dropped.
+    pub fn track_websocket(&self) -> ConnectionGuard<'_> {
+        self.websocket_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(&self.websocket_connections)
+    }
+
+}
+
+/// Keeps a connection counted while it is alive.
+pub struct ConnectionGuard<'a>(&'a AtomicI64);
+
+impl Drop for ConnectionGuard<'_> {
+
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+}
+
+/// A counter split by label values, in the order of the label names it is
+/// exported with.
+#[derive(Default)]
+pub struct CounterVec {
+    values: Mutex<BTreeMap<Vec<String>, u64>>
+}
+
+impl CounterVec {
+
+    pub fn increment(&self, labels: &[&str]) {
+        let key = labels.iter().map(|label| label.to_string()).collect();
+        *self.values.lock().unwrap().entry(key).or_default() += 1;
+    }
+
+}
+
+#[derive(Default, Clone)]
+struct Buckets {
+    /// Observations at or below each bound, not yet cumulative.
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64
+}
+
+/// A histogram with fixed buckets, split by label values like [`CounterVec`].
+pub struct Histogram {
+    bounds: &'static [f64],
+    values: Mutex<BTreeMap<Vec<String>, Buckets>>
+}
+
+impl Histogram {
+
+    fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            values: Mutex::new(BTreeMap::new())
+        }
+    }
+
+    pub fn observe(&self, labels: &[&str], value: f64) {
+        let key = labels.iter().map(|label| label.to_string()).collect();
+        let mut values = self.values.lock().unwrap();
+        let buckets = values.entry(key).or_insert_with(|| Buckets {
+            counts: vec![0; self.bounds.len()],
+            ..Buckets::default()
+        });
+
+        if let Some(bucket) = self.bounds.iter().position(|bound| value <= *bound) {
+            buckets.counts[bucket] += 1;
+        }
+
+        buckets.sum += value;
+        buckets.count += 1;
+    }
+
+}
+
+/// Everything in `state` in the Prometheus text format.
+pub(crate) async fn render(state: &State) -> String {
+    let metrics = &state.metrics;
+    let mut out = Exposition::default();
+
+    out.counter("cache_hits_total", "Conversions served from the cache.", metrics.cache_hits.load(Ordering::Relaxed));
+    out.counter("cache_misses_total", "Conversions not found in the cache.", metrics.cache_misses.load(Ordering::Relaxed));
+    out.counter_vec("conversions_total", "Uploads accepted for conversion.", &["source_format", "target_format"], &metrics.conversions);
+    out.counter_vec("failures_total", "Jobs that failed.", &["stage", "reason"], &metrics.failures);
+    out.histogram("job_duration_seconds", "Time from submitting a job until its result was processed.", &[], &metrics.job_duration);
+    out.counter("upload_bytes_total", "Bytes uploaded for conversion.", metrics.upload_bytes.load(Ordering::Relaxed));
+    out.counter("download_bytes_total", "Bytes of converted files downloaded.", metrics.download_bytes.load(Ordering::Relaxed));
+
+    out.histogram("backend_request_duration_seconds", "Time CloudConvert took to answer.", &["operation"], &metrics.backend_latency);
+    out.gauge("credits_remaining", "CloudConvert credits left as of the last check.", metrics.credits_remaining.load(Ordering::Relaxed));
+    out.counter("credits_used_total", "CloudConvert credits charged for jobs.", metrics.credits_used.load(Ordering::Relaxed));
+
+    out.gauge("websocket_connections", "Open websocket connections.", metrics.websocket_connections.load(Ordering::Relaxed));
+    out.gauge("pending_jobs", "Jobs kept in memory until they end.", state.pending_jobs.read().await.len() as i64);
+
+    let pool = state.pool.state();
+    out.gauge("db_pool_connections", "Database connections open.", pool.connections as i64);
+    out.gauge("db_pool_idle_connections", "Database connections open but unused.", pool.idle_connections as i64);
+
+    out.0
+}
+
+#[derive(Default)]
+struct Exposition(String);
+
+impl Exposition {
+
+    fn header(&mut self, name: &str, help: &str, kind: &str) {
+        let _ = writeln!(self.0, "# HELP {}_{} {}", NAMESPACE, name, help);
+        let _ = writeln!(self.0, "# TYPE {}_{} {}", NAMESPACE, name, kind);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+        let _ = write!(self.0, "{}_{}", NAMESPACE, name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels.iter()
+                .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+                .collect();
+
+            let _ = write!(self.0, "{{{}}}", labels.join(","));
+        }
+
+        let _ = writeln!(self.0, " {}", value);
+    }
+
+    fn counter(&mut self, name: &str, help: &str, value: u64) {
+        self.header(name, help, "counter");
+        self.sample(name, &[], value);
+    }
+
+    fn gauge(&mut self, name: &str, help: &str, value: i64) {
+        self.header(name, help, "gauge");
+        self.sample(name, &[], value);
+    }
+
+    fn counter_vec(&mut self, name: &str, help: &str, label_names: &[&str], counter: &CounterVec) {
+        self.header(name, help, "counter");
+        for (values, count) in counter.values.lock().unwrap().iter() {
+            let labels: Vec<(&str, &str)> = label_names.iter().copied().zip(values.iter().map(String::as_str)).collect();
+            self.sample(name, &labels, count);
+        }
+    }
+
+    fn histogram(&mut self, name: &str, help: &str, label_names: &[&str], histogram: &Histogram) {
+        self.header(name, help, "histogram");
+        let bucket_name = format!("{}_bucket", name);
+        for (values, buckets) in histogram.values.lock().unwrap().iter() {
+            let labels: Vec<(&str, &str)> = label_names.iter().copied().zip(values.iter().map(String::as_str)).collect();
+
+            let mut cumulative = 0;
+            for (bound, count) in histogram.bounds.iter().zip(&buckets.counts) {
+                cumulative += count;
+                let bound = bound.to_string();
+                let mut bucket_labels = labels.clone();
+                bucket_labels.push(("le", &bound));
+                self.sample(&bucket_name, &bucket_labels, cumulative);
+            }
+
+            let mut bucket_labels = labels.clone();
+            bucket_labels.push(("le", "+Inf"));
+            self.sample(&bucket_name, &bucket_labels, buckets.count);
+            self.sample(&format!("{}_sum", name), &labels, buckets.sum);
+            self.sample(&format!("{}_count", name), &labels, buckets.count);
+        }
+    }
+
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CounterVec, Exposition, Histogram};
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let histogram = Histogram::new(&[1.0, 5.0]);
+        histogram.observe(&["submit"], 0.5);
+        histogram.observe(&["submit"], 3.0);
+        histogram.observe(&["submit"], 7.0);
+
+        let mut out = Exposition::default();
+        out.histogram("latency", "Latency.", &["operation"], &histogram);
+
+        assert_eq!(out.0, "\
+# HELP file_converter_latency Latency.
+# TYPE file_converter_latency histogram
+file_converter_latency_bucket{operation=\"submit\",le=\"1\"} 1
+file_converter_latency_bucket{operation=\"submit\",le=\"5\"} 2
+file_converter_latency_bucket{operation=\"submit\",le=\"+Inf\"} 3
+file_converter_latency_sum{operation=\"submit\"} 10.5
+file_converter_latency_count{operation=\"submit\"} 3
+");
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        let counter = CounterVec::default();
+        counter.increment(&["say \"hi\"\nC:\\"]);
+        counter.increment(&["say \"hi\"\nC:\\"]);
+
+        let mut out = Exposition::default();
+        out.counter_vec("failures_total", "Failures.", &["reason"], &counter);
+
+        assert!(out.0.ends_with("file_converter_failures_total{reason=\"say \\\"hi\\\"\\nC:\\\\\"} 2\n"));
+    }
+
+    #[test]
+    fn unlabelled_samples_have_no_braces() {
+        let mut out = Exposition::default();
+        out.gauge("pending_jobs", "Pending jobs.", -1);
+
+        assert!(out.0.ends_with("file_converter_pending_jobs -1\n"));
+    }
+}
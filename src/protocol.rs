@@ -0,0 +1,131 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Version of the websocket protocol, sent as `v` with every message.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Messages the server sends over `/ws` and `/events`, serialized as
+/// `{"v": 1, "type": "job-completed", ...}`.
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum ServerMessage {
+    /// Reply to the client's hello once the socket is registered.
+    Hello,
+
+    /// The job was accepted and waits to be handed to the converter.
+    JobQueued { job_id: String, file_name: String },
+
+    /// Where the job is in the queue, `1` being the next one submitted.
+    JobWaiting { job_id: String, position: usize },
+
+    /// The job moved on to another stage.
+    JobProgress { job_id: String, stage: JobStage },
+
+    /// The converted file is ready at `/files/{file_id}`.
+    JobCompleted { job_id: String, file_id: i32 },
+
+    JobFailed { job_id: String, reason: String },
+
+    /// The job was stopped before it ended.
+    JobCancelled { job_id: String },
+
+    /// Sent periodically so proxies keep idle sockets open.
+    Heartbeat,
+
+    /// The connection was refused or the client sent something invalid.
+    Error { reason: String }
+}
+
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum JobStage {
+    Converting,
+    Downloading,
+    Storing
+}
+
+impl fmt::Display for JobStage {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            JobStage::Converting => "converting",
+            JobStage::Downloading => "downloading",
+            JobStage::Storing => "storing"
+        })
+    }
+
+}
+
+/// A job message numbered for replay. Ids grow across every session, so a
+/// client can resume from the last id it saw.
+#[derive(Clone, Debug)]
+pub struct Event {
+    pub id: u64,
+    pub message: ServerMessage
+}
+
+impl ServerMessage {
+
+    /// The job this message is about, if any.
+    pub fn job_id(&self) -> Option<&str> {
+        match self {
+            ServerMessage::JobQueued { job_id, .. }
+            | ServerMessage::JobWaiting { job_id, .. }
+            | ServerMessage::JobProgress { job_id, .. }
+            | ServerMessage::JobCompleted { job_id, .. }
+            | ServerMessage::JobFailed { job_id, .. }
+            | ServerMessage::JobCancelled { job_id } => Some(job_id),
+            _ => None
+        }
+    }
+
+    /// Whether no further messages will follow for this job.
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            ServerMessage::JobCompleted { .. } | ServerMessage::JobFailed { .. } | ServerMessage::JobCancelled { .. }
+        )
+    }
+
+    pub fn to_json(&self) -> String {
+        #[derive(Serialize)]
+        struct Envelope<'a> {
+            v: u32,
+            #[serde(flatten)]
+            message: &'a ServerMessage
+        }
+
+        serde_json::to_string(&Envelope { v: PROTOCOL_VERSION, message: self }).unwrap()
+    }
+
+}
+
+/// Messages the browser sends over `/ws`.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum ClientMessage {
+    /// First message on every connection. The session comes from the cookie;
+    /// a client that names one anyway must name that same session.
+    Hello {
+        #[serde(default)]
+        session_id: Option<String>
+    },
+
+    Heartbeat {}
+}
+
+#[derive(Deserialize)]
+pub struct ClientEnvelope {
+    pub v: u32,
+    #[serde(flatten)]
+    pub message: ClientMessage
+}
+
+impl ClientEnvelope {
+
+    pub fn parse(text: &str) -> Option<ClientEnvelope> {
+        serde_json::from_str(text).ok()
+    }
+
+}
@@ -8,14 +8,27 @@ pub struct CreateResponse {
 #[derive(Deserialize)]
 pub struct Job {
     pub id: String,
+    /// Our own job id, set when the job was created.
+    pub tag: Option<String>,
     pub tasks: Vec<JobTask>
 }
 
 #[derive(Deserialize)]
 pub struct JobTask {
     pub id: String,
+    pub name: String,
     pub operation: String,
-    pub result: TaskResult
+    /// `finished` or `error`, among others.
+    #[serde(default)]
+    pub status: Option<String>,
+    /// Why the task failed, if it did.
+    #[serde(default)]
+    pub message: Option<String>,
+    /// What CloudConvert charged for the task.
+    #[serde(default)]
+    pub credits: Option<i32>,
+    /// Missing when the task failed, which ignored preview tasks may do.
+    pub result: Option<TaskResult>
 }
 
 #[derive(Deserialize)]
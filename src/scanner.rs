@@ -0,0 +1,145 @@
+use std::{env, time::Duration};
+
+use anyhow::{anyhow, Result};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpStream, UnixStream}
+};
+use tracing::info;
+
+/// Size of each INSTREAM chunk, well below clamd's default `StreamMaxLength`.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// How long a scan may take, from connecting to clamd until its reply.
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+/// Virus scanner every upload and conversion result passes through.
+pub enum Scanner {
+    /// Scanning is turned off, every file is reported clean.
+    Disabled,
+
+    /// A ClamAV daemon reachable over TCP (`host:port`) or a unix socket
+    /// (`unix:/path/to/clamd.sock`).
+    Clamd { address: String, timeout: Duration }
+}
+
+pub enum ScanResult {
+    Clean,
+    /// The file matched the named signature.
+    Infected(String)
+}
+
+impl Scanner {
+
+    /// Uses the daemon at `CLAMD_ADDRESS`, or disables scanning when unset.
+    /// Scans taking longer than `CLAMD_TIMEOUT_SECS` fail.
+    pub fn from_env() -> Self {
+        match env::var("CLAMD_ADDRESS") {
+            Ok(address) if !address.is_empty() => {
+                let timeout = env::var("CLAMD_TIMEOUT_SECS")
+                    .ok()
+                    .and_then(|timeout| timeout.parse().ok())
+                    .unwrap_or(DEFAULT_TIMEOUT_SECS);
+
+                info!("Scanning files with clamd at {}", address);
+                Scanner::Clamd { address, timeout: Duration::from_secs(timeout) }
+            },
+            _ => {
+                info!("CLAMD_ADDRESS is not set, virus scanning is disabled!");
+                Scanner::Disabled
+            }
+        }
+    }
+
+    pub async fn scan(&self, bytes: &[u8]) -> Result<ScanResult> {
+        match self {
+            Scanner::Disabled => Ok(ScanResult::Clean),
+            Scanner::Clamd { address, timeout } => {
+                // A stuck clamd must not hold up uploads and results forever.
+                let reply = tokio::time::timeout(*timeout, async {
+                    match address.strip_prefix("unix:") {
+                        Some(path) => instream(UnixStream::connect(path).await?, bytes).await,
+                        None => instream(TcpStream::connect(address).await?, bytes).await
+                    }
+                }).await;
+
+                match reply {
+                    Ok(reply) => parse_reply(&reply?),
+                    Err(_) => Err(anyhow!("clamd did not answer within {}s", timeout.as_secs()))
+                }
+            }
+        }
+    }
+
+}
+
+/// Sends `bytes` to clamd using the INSTREAM command and returns its reply.
+async fn instream<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, bytes: &[u8]) -> Result<String> {
+    stream.write_all(b"zINSTREAM\0").await?;
+    for chunk in bytes.chunks(CHUNK_SIZE) {
+        stream.write_all(&(chunk.len() as u32).to_be_bytes()).await?;
+        stream.write_all(chunk).await?;
+    }
+
+    stream.write_all(&0u32.to_be_bytes()).await?;
+    stream.flush().await?;
+
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply).await?;
+
+    Ok(String::from_utf8_lossy(&reply).trim_end_matches('\0').trim().to_string())
+}
+
+/// Replies look like `stream: OK`, `stream: <signature> FOUND` or
+/// `<reason> ERROR`.
+fn parse_reply(reply: &str) -> Result<ScanResult> {
+    let status = reply.strip_prefix("stream:").unwrap_or(reply).trim();
+    if status == "OK" {
+        Ok(ScanResult::Clean)
+    } else if let Some(signature) = status.strip_suffix("FOUND") {
+        Ok(ScanResult::Infected(signature.trim().to_string()))
+    } else {
+        Err(anyhow!("clamd could not scan the file: {}", reply))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::net::TcpListener;
+
+    use super::{parse_reply, ScanResult, Scanner};
+
+    #[test]
+    fn parses_clean_and_infected_replies() {
+        assert!(matches!(parse_reply("stream: OK"), Ok(ScanResult::Clean)));
+        assert!(matches!(
+            parse_reply("stream: Eicar-Signature FOUND"),
+            Ok(ScanResult::Infected(signature)) if signature == "Eicar-Signature"
+        ));
+    }
+
+    #[test]
+    fn fails_on_errors() {
+        assert!(parse_reply("INSTREAM size limit exceeded. ERROR").is_err());
+        assert!(parse_reply("").is_err());
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_a_stuck_clamd() {
+        // Accepts the connection but never answers.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let stuck = tokio::spawn(async move {
+            let connection = listener.accept().await;
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            drop(connection);
+        });
+
+        let scanner = Scanner::Clamd { address, timeout: Duration::from_millis(100) };
+        let scanned = tokio::time::timeout(Duration::from_secs(5), scanner.scan(b"content")).await;
+        assert!(matches!(scanned, Ok(Err(err)) if err.to_string().contains("did not answer")));
+        stuck.abort();
+    }
+}
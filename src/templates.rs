@@ -1,13 +1,18 @@
 use askama::Template;
 
-use crate::database::models::File;
+use crate::{
+    converter::{credits::{LowCreditAction, Reading}, jobs::JobStatus},
+    database::{models::{display_size, File, FileSummary, InboxEntry, Job, OwnedFile}, retention::Purged},
+    protocol::JobStage,
+    webhook::inbox::InboxStatus,
+    ConnectedSession
+};
 
 #[derive(Template)]
 #[template(path = "index.html")]
 #[allow(dead_code)]
 pub(crate) struct Index {
     pub(crate) authorized_extensions: String,
-    pub(crate) session_id: String,
     pub(crate) website_url: String
 }
 
@@ -17,20 +22,159 @@ pub(crate) struct Index {
 #[allow(dead_code)]
 pub(crate) struct FileInfo {
     pub(crate) download_uri: String,
-    pub(crate) file_name: String
+    pub(crate) file: File
 }
 
 #[derive(Template)]
-#[template(path = "search/page.html")]
-#[allow(dead_code)]
-pub(crate) struct Search;
+#[template(path = "view.html")]
+pub(crate) struct FileViewer {
+    pub(crate) view_uri: String,
+    pub(crate) download_uri: String,
+    pub(crate) file: FileSummary
+}
 
 #[derive(Template)]
 #[template(path = "search/results.html")]
 #[allow(dead_code)]
 pub(crate) struct SearchResults {
-    pub files: Vec<File>,
-    pub search_term: String
+    pub files: Vec<SearchHit>,
+    pub search_term: String,
+    /// Later pages are appended to the existing list instead of replacing it.
+    pub first_page: bool,
+    pub next_page: Option<i64>
+}
+
+pub(crate) struct SearchHit {
+    pub file: FileSummary,
+    /// The file name split into matching and non-matching segments.
+    pub name: Vec<Highlight>,
+    /// Document text around the matches, empty when the text didn't match.
+    pub snippet: Vec<Highlight>
+}
+
+pub(crate) struct Highlight {
+    pub text: String,
+    pub matched: bool
+}
+
+/// Target formats offered by the search filter.
+pub(crate) const SEARCH_FORMATS: [&str; 3] = ["pdf", "docx", "pptx"];
+
+#[derive(Template)]
+#[template(path = "search/page.html")]
+pub(crate) struct Search {
+    pub formats: [&'static str; 3]
+}
+
+#[derive(Template)]
+#[template(path = "job.html")]
+pub(crate) struct JobPage {
+    pub(crate) job: Job,
+    /// What a running job is doing right now, if we know.
+    pub(crate) stage: Option<JobStage>,
+    /// Where a queued job is in the queue, if we know.
+    pub(crate) position: Option<usize>,
+    pub(crate) refresh_seconds: u32
+}
+
+#[derive(Template)]
+#[template(path = "history.html")]
+pub(crate) struct History {
+    pub(crate) jobs: Vec<Job>,
+    /// Keep refreshing while any job is still running.
+    pub(crate) in_progress: bool,
+    pub(crate) refresh_seconds: u32
+}
+
+#[derive(Template)]
+#[template(path = "admin/login.html")]
+pub(crate) struct AdminLogin {
+    /// Whether `ADMIN_PASSWORD` is set at all.
+    pub(crate) enabled: bool,
+    pub(crate) failed: bool
+}
+
+#[derive(Template)]
+#[template(path = "admin/overview.html")]
+pub(crate) struct AdminOverview {
+    pub(crate) jobs_by_status: Vec<(JobStatus, i64)>,
+    pub(crate) file_count: i64,
+    pub(crate) file_bytes: i64,
+    pub(crate) sessions: usize,
+    pub(crate) retention_days: i64,
+    /// Set right after a purge.
+    pub(crate) purged: Option<Purged>
+}
+
+impl AdminOverview {
+
+    fn display_file_bytes(&self) -> String {
+        display_size(self.file_bytes)
+    }
+
+}
+
+#[derive(Template)]
+#[template(path = "admin/jobs.html")]
+pub(crate) struct AdminJobs {
+    pub(crate) jobs: Vec<Job>,
+    pub(crate) status: Option<JobStatus>,
+    pub(crate) statuses: [JobStatus; 5]
+}
+
+impl AdminJobs {
+
+    fn is_selected(&self, candidate: &JobStatus) -> bool {
+        self.status == Some(*candidate)
+    }
+
+}
+
+#[derive(Template)]
+#[template(path = "admin/files.html")]
+pub(crate) struct AdminFiles {
+    pub(crate) files: Vec<OwnedFile>
+}
+
+#[derive(Template)]
+#[template(path = "admin/sessions.html")]
+pub(crate) struct AdminSessions {
+    pub(crate) sessions: Vec<ConnectedSession>
+}
+
+#[derive(Template)]
+#[template(path = "admin/webhooks.html")]
+pub(crate) struct AdminWebhooks {
+    pub(crate) entries: Vec<InboxEntry>,
+    pub(crate) status: Option<InboxStatus>,
+    pub(crate) statuses: [InboxStatus; 5]
+}
+
+impl AdminWebhooks {
+
+    fn is_selected(&self, candidate: &InboxStatus) -> bool {
+        self.status == Some(*candidate)
+    }
+
+}
+
+#[derive(Template)]
+#[template(path = "admin/webhook.html")]
+pub(crate) struct AdminWebhook {
+    pub(crate) entry: InboxEntry
+}
+
+#[derive(Template)]
+#[template(path = "admin/credits.html")]
+pub(crate) struct AdminCredits {
+    /// `None` until the first check worked.
+    pub(crate) reading: Option<Reading>,
+    pub(crate) low: bool,
+    pub(crate) threshold: i64,
+    pub(crate) action: LowCreditAction,
+    pub(crate) used_today: i64,
+    pub(crate) used_month: i64,
+    pub(crate) jobs: Vec<Job>
 }
 
 #[derive(Template)]
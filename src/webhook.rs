@@ -0,0 +1,287 @@
+pub mod deliveries;
+pub mod inbox;
+
+use std::{collections::HashSet, env, sync::Arc};
+
+use axum::http::HeaderMap;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio::sync::{mpsc, Mutex, Semaphore};
+use tracing::{error, info};
+
+use self::{deliveries::{JOB_FAILED, JOB_FINISHED}, inbox::InboxStatus};
+
+use crate::{endpoints::webhooks::{self, failed, finished}, response::Job, SharedState};
+
+/// How many webhooks may wait for a worker before new ones are refused.
+const DEFAULT_QUEUE_SIZE: usize = 256;
+
+/// How many webhooks are processed at once.
+const DEFAULT_WORKERS: usize = 4;
+
+/// Header CloudConvert sends the HMAC of the body in.
+const SIGNATURE_HEADER: &str = "CloudConvert-Signature";
+
+/// The CloudConvert events we handle, each sent to its own endpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WebhookEvent {
+    Finished,
+    Failed
+}
+
+impl WebhookEvent {
+
+    /// The event as CloudConvert names it.
+    pub fn name(&self) -> &'static str {
+        match self {
+            WebhookEvent::Finished => JOB_FINISHED,
+            WebhookEvent::Failed => JOB_FAILED
+        }
+    }
+
+    /// The endpoint under `/webhooks` the event is sent to, stored with each
+    /// webhook so replays know where to go.
+    pub fn endpoint(&self) -> &'static str {
+        match self {
+            WebhookEvent::Finished => "finished",
+            WebhookEvent::Failed => "failed"
+        }
+    }
+
+    pub fn from_endpoint(endpoint: &str) -> Option<WebhookEvent> {
+        [WebhookEvent::Finished, WebhookEvent::Failed].into_iter().find(|event| event.endpoint() == endpoint)
+    }
+
+}
+
+/// A job that ended waiting for a worker, with the inbox entry it came from.
+pub struct QueuedJob {
+    pub inbox_id: i32,
+    pub event: WebhookEvent,
+    pub job: Job,
+    /// Whether an admin replayed the webhook.
+    pub replay: bool
+}
+
+/// What became of a job handed to [`Queue::push`].
+pub enum Pushed {
+    Queued,
+    /// Another delivery of the same job is waiting or being processed.
+    AlreadyQueued,
+    /// The queue is full and the webhook should be retried later.
+    Full
+}
+
+/// Webhooks that were acknowledged but not yet processed.
+pub struct Queue {
+    sender: mpsc::Sender<QueuedJob>,
+    /// Taken by [`start_workers`] once the state is shared.
+    receiver: Mutex<Option<mpsc::Receiver<QueuedJob>>>,
+    /// Backend jobs waiting for or held by a worker, so a repeated delivery
+    /// isn't processed alongside the first.
+    queued: std::sync::Mutex<HashSet<String>>
+}
+
+impl Queue {
+
+    /// Sized by `WEBHOOK_QUEUE_SIZE`.
+    pub fn from_env() -> Self {
+        let size = env::var("WEBHOOK_QUEUE_SIZE")
+            .ok()
+            .and_then(|size| size.parse().ok())
+            .unwrap_or(DEFAULT_QUEUE_SIZE);
+
+        let (sender, receiver) = mpsc::channel(size);
+        Queue {
+            sender,
+            receiver: Mutex::new(Some(receiver)),
+            queued: std::sync::Mutex::new(HashSet::new())
+        }
+    }
+
+    /// Hands a job that ended to the workers.
+    pub fn push(&self, job: QueuedJob) -> Pushed {
+        let mut queued = self.queued.lock().unwrap();
+        if queued.contains(&job.job.id) {
+            return Pushed::AlreadyQueued
+        }
+
+        let backend_job_id = job.job.id.clone();
+        match self.sender.try_send(job) {
+            Ok(()) => {
+                queued.insert(backend_job_id);
+                Pushed::Queued
+            },
+            Err(_) => Pushed::Full
+        }
+    }
+
+    /// Waits until a job can be pushed without the queue being full.
+    async fn wait_for_room(&self) {
+        // The permit is handed back right away, it only tells us there is room.
+        let _ = self.sender.reserve().await;
+    }
+
+    fn done(&self, backend_job_id: &str) {
+        self.queued.lock().unwrap().remove(backend_job_id);
+    }
+
+}
+
+/// Whether the body was signed with `CLOUDCONVERT_WEBHOOK_SECRET`, i.e. was
+/// sent by CloudConvert. Without a secret no webhook is trusted.
+pub fn is_signed(headers: &HeaderMap, body: &str) -> bool {
+    let Some(secret) = env::var("CLOUDCONVERT_WEBHOOK_SECRET").ok().filter(|secret| !secret.is_empty()) else {
+        error!("CLOUDCONVERT_WEBHOOK_SECRET is not set, refusing webhook!");
+        return false
+    };
+
+    verify(&secret, headers, body)
+}
+
+fn verify(secret: &str, headers: &HeaderMap, body: &str) -> bool {
+    let signature = headers.get(SIGNATURE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| hex::decode(value.trim()).ok());
+
+    let Some(signature) = signature else {
+        return false
+    };
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC takes keys of any length!");
+    mac.update(body.as_bytes());
+
+    // Compares in constant time.
+    mac.verify_slice(&signature).is_ok()
+}
+
+/// Processes queued webhooks on at most `WEBHOOK_WORKERS` tasks at a time.
+pub async fn start_workers(state: SharedState) {
+    let receiver = state.webhooks.receiver.lock().await.take();
+    let Some(mut receiver) = receiver else {
+        error!("Webhook workers were already started!");
+        return
+    };
+
+    let workers = env::var("WEBHOOK_WORKERS")
+        .ok()
+        .and_then(|workers| workers.parse().ok())
+        .filter(|workers| *workers > 0)
+        .unwrap_or(DEFAULT_WORKERS);
+
+    info!("Processing webhooks with {} workers", workers);
+    let permits = Arc::new(Semaphore::new(workers));
+    let workers_state = state.clone();
+    tokio::spawn(async move {
+        while let Some(job) = receiver.recv().await {
+            let permit = permits.clone().acquire_owned().await.unwrap();
+            let state = workers_state.clone();
+            tokio::spawn(async move {
+                process(&state, job).await;
+                drop(permit);
+            });
+        }
+    });
+
+    tokio::spawn(async move {
+        requeue(&state).await;
+    });
+}
+
+/// Queues webhooks again that were accepted but not processed when the
+/// server stopped, as the queue only lives in memory.
+async fn requeue(state: &SharedState) {
+    let mut conn = match state.pool.get().await {
+        Ok(conn) => conn,
+        Err(err) => {
+            error!("Unable to connect to database to queue unfinished webhooks: {}", err);
+            return
+        }
+    };
+
+    let entries = match inbox::unfinished(&mut conn).await {
+        Ok(entries) => entries,
+        Err(err) => {
+            error!("Unable to look up unfinished webhooks: {}", err);
+            return
+        }
+    };
+
+    if !entries.is_empty() {
+        info!("Queueing {} unfinished webhooks again", entries.len());
+    }
+
+    for entry in entries {
+        let Some(event) = WebhookEvent::from_endpoint(&entry.endpoint) else {
+            continue
+        };
+
+        state.webhooks.wait_for_room().await;
+        webhooks::accept(state, &mut conn, entry.id, event, &entry.body, false).await;
+    }
+}
+
+async fn process(state: &SharedState, QueuedJob { inbox_id, event, job, replay }: QueuedJob) {
+    let backend_job_id = job.id.clone();
+    let mut conn = match state.pool.get().await {
+        Ok(conn) => conn,
+        Err(err) => {
+            error!("[Webhook {}] Unable to connect to database: {}", inbox_id, err);
+            state.webhooks.done(&backend_job_id);
+            return
+        }
+    };
+
+    let processed = match event {
+        WebhookEvent::Finished => finished::process(state, &mut conn, job, replay).await,
+        WebhookEvent::Failed => failed::process(state, &mut conn, job, replay).await
+    };
+    state.webhooks.done(&backend_job_id);
+
+    let (status, error) = match processed {
+        Ok(()) => (InboxStatus::Processed, None),
+        Err(reason) => (InboxStatus::Failed, Some(reason))
+    };
+
+    if let Err(err) = inbox::set_status(&mut conn, inbox_id, status, error.as_deref()).await {
+        error!("[Webhook {}] Unable to record outcome: {}", inbox_id, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderMap, HeaderValue};
+
+    use super::{verify, SIGNATURE_HEADER};
+
+    const BODY: &str = r#"{"event":"job.finished"}"#;
+
+    /// HMAC-SHA256 of `BODY` with the key "secret".
+    const SIGNATURE: &str = "3431a66bd552a0cc4047145baf9330054b9d8a57d142066bf9246fd6f4478403";
+
+    fn signed(signature: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(SIGNATURE_HEADER, HeaderValue::from_str(signature).unwrap());
+        headers
+    }
+
+    #[test]
+    fn accepts_signed_body() {
+        assert!(verify("secret", &signed(SIGNATURE), BODY));
+        assert!(verify("secret", &signed(&SIGNATURE.to_uppercase()), BODY));
+    }
+
+    #[test]
+    fn rejects_missing_or_malformed_signature() {
+        assert!(!verify("secret", &HeaderMap::new(), BODY));
+        assert!(!verify("secret", &signed("not hex"), BODY));
+        assert!(!verify("secret", &signed(&SIGNATURE[..32]), BODY));
+    }
+
+    #[test]
+    fn rejects_other_secret_or_body() {
+        assert!(!verify("other", &signed(SIGNATURE), BODY));
+        assert!(!verify("secret", &signed(SIGNATURE), r#"{"event":"job.failed"}"#));
+    }
+}
@@ -0,0 +1,68 @@
+use chrono::{DateTime, Utc};
+use diesel::{ExpressionMethods, QueryDsl, QueryResult};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+
+use crate::database::schema::webhook_deliveries;
+
+/// The event CloudConvert sends to `/webhooks/finished`.
+pub const JOB_FINISHED: &str = "job.finished";
+
+/// The event CloudConvert sends to `/webhooks/failed`.
+pub const JOB_FAILED: &str = "job.failed";
+
+pub enum Delivery {
+    /// First time we see this event for the job, or earlier deliveries were
+    /// never processed, e.g. because the server stopped.
+    New,
+    /// CloudConvert retried an event we already processed, which stored
+    /// `file_id` if it was processed successfully.
+    Duplicate { file_id: Option<i32> }
+}
+
+/// Records that `event` arrived for the job, telling a first delivery from
+/// a retry.
+pub async fn record(conn: &mut AsyncPgConnection, backend_job_id: &str, event: &str) -> QueryResult<Delivery> {
+    let inserted = diesel::insert_into(webhook_deliveries::table)
+        .values((
+            webhook_deliveries::backend_job_id.eq(backend_job_id),
+            webhook_deliveries::event.eq(event)
+        ))
+        .on_conflict_do_nothing()
+        .execute(conn)
+        .await?;
+
+    if inserted == 1 {
+        return Ok(Delivery::New)
+    }
+
+    let (processed_at, file_id) = webhook_deliveries::table
+        .find((backend_job_id, event))
+        .select((webhook_deliveries::processed_at, webhook_deliveries::file_id))
+        .first::<(Option<DateTime<Utc>>, Option<i32>)>(conn)
+        .await?;
+
+    match processed_at {
+        Some(_) => Ok(Delivery::Duplicate { file_id }),
+        None => Ok(Delivery::New)
+    }
+}
+
+/// Forgets a delivery we could not accept, so its retry is processed.
+pub async fn forget(conn: &mut AsyncPgConnection, backend_job_id: &str, event: &str) -> QueryResult<()> {
+    diesel::delete(webhook_deliveries::table.find((backend_job_id, event)))
+        .execute(conn)
+        .await
+        .map(|_| ())
+}
+
+/// Marks the delivery as processed, remembering the file it produced.
+pub async fn processed(conn: &mut AsyncPgConnection, backend_job_id: &str, event: &str, file_id: Option<i32>) -> QueryResult<()> {
+    diesel::update(webhook_deliveries::table.find((backend_job_id, event)))
+        .set((
+            webhook_deliveries::processed_at.eq(Utc::now()),
+            webhook_deliveries::file_id.eq(file_id)
+        ))
+        .execute(conn)
+        .await
+        .map(|_| ())
+}
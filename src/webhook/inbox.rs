@@ -0,0 +1,142 @@
+use std::fmt;
+
+use axum::http::HeaderMap;
+use chrono::Utc;
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, QueryResult, SelectableHelper};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde_json::{Map, Value};
+
+use crate::database::{models::InboxEntry, schema::webhook_inbox};
+
+/// What became of a stored webhook, as kept in `webhook_inbox.status`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InboxStatus {
+    /// Stored, not yet looked at.
+    Received,
+    /// Accepted and waiting for a worker.
+    Queued,
+    Processed,
+    /// Nothing to do, e.g. a repeated delivery or an event we don't handle.
+    Ignored,
+    /// Dead letter, kept for inspection and replay.
+    Failed
+}
+
+impl InboxStatus {
+
+    pub const ALL: [InboxStatus; 5] = [
+        InboxStatus::Received,
+        InboxStatus::Queued,
+        InboxStatus::Processed,
+        InboxStatus::Ignored,
+        InboxStatus::Failed
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InboxStatus::Received => "received",
+            InboxStatus::Queued => "queued",
+            InboxStatus::Processed => "processed",
+            InboxStatus::Ignored => "ignored",
+            InboxStatus::Failed => "failed"
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<InboxStatus> {
+        InboxStatus::ALL.into_iter().find(|candidate| candidate.as_str() == status)
+    }
+
+}
+
+impl fmt::Display for InboxStatus {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+
+}
+
+/// Stores a webhook request exactly as it arrived, returning its id.
+pub async fn store(conn: &mut AsyncPgConnection, endpoint: &str, headers: &HeaderMap, body: &str) -> QueryResult<i32> {
+    let headers: Map<String, Value> = headers.iter()
+        .map(|(name, value)| (
+            name.to_string(),
+            Value::String(String::from_utf8_lossy(value.as_bytes()).into_owned())
+        ))
+        .collect();
+
+    diesel::insert_into(webhook_inbox::table)
+        .values((
+            webhook_inbox::endpoint.eq(endpoint),
+            webhook_inbox::headers.eq(Value::Object(headers)),
+            webhook_inbox::body.eq(body)
+        ))
+        .returning(webhook_inbox::id)
+        .get_result(conn)
+        .await
+}
+
+pub async fn find(conn: &mut AsyncPgConnection, id: i32) -> QueryResult<Option<InboxEntry>> {
+    webhook_inbox::table
+        .find(id)
+        .select(InboxEntry::as_select())
+        .first(conn)
+        .await
+        .optional()
+}
+
+/// The newest stored webhooks, optionally only those with `status`.
+pub async fn list(conn: &mut AsyncPgConnection, status: Option<InboxStatus>, limit: i64) -> QueryResult<Vec<InboxEntry>> {
+    let mut query = webhook_inbox::table
+        .order(webhook_inbox::received_at.desc())
+        .limit(limit)
+        .select(InboxEntry::as_select())
+        .into_boxed();
+
+    if let Some(status) = status {
+        query = query.filter(webhook_inbox::status.eq(status.as_str()));
+    }
+
+    query.load(conn).await
+}
+
+/// Webhooks that were stored or queued but never processed, oldest first.
+pub async fn unfinished(conn: &mut AsyncPgConnection) -> QueryResult<Vec<InboxEntry>> {
+    webhook_inbox::table
+        .filter(webhook_inbox::status.eq_any([InboxStatus::Received.as_str(), InboxStatus::Queued.as_str()]))
+        .order(webhook_inbox::received_at.asc())
+        .select(InboxEntry::as_select())
+        .load(conn)
+        .await
+}
+
+pub async fn set_status(conn: &mut AsyncPgConnection, id: i32, status: InboxStatus, error: Option<&str>) -> QueryResult<()> {
+    let processed_at = match status {
+        InboxStatus::Received | InboxStatus::Queued => None,
+        _ => Some(Utc::now())
+    };
+
+    diesel::update(webhook_inbox::table.find(id))
+        .set((
+            webhook_inbox::status.eq(status.as_str()),
+            webhook_inbox::error.eq(error),
+            webhook_inbox::processed_at.eq(processed_at)
+        ))
+        .execute(conn)
+        .await
+        .map(|_| ())
+}
+
+/// Counts another attempt at a stored webhook before it is replayed.
+pub async fn retry(conn: &mut AsyncPgConnection, id: i32) -> QueryResult<()> {
+    diesel::update(webhook_inbox::table.find(id))
+        .set((
+            webhook_inbox::status.eq(InboxStatus::Received.as_str()),
+            webhook_inbox::error.eq(None::<String>),
+            webhook_inbox::processed_at.eq(None::<chrono::DateTime<Utc>>),
+            webhook_inbox::attempts.eq(webhook_inbox::attempts + 1)
+        ))
+        .execute(conn)
+        .await
+        .map(|_| ())
+}
@@ -1,33 +1,145 @@
 
+// Must match `PROTOCOL_VERSION` in src/protocol.rs.
+const PROTOCOL_VERSION = 1;
+
 window.addEventListener('DOMContentLoaded', () => {
 	let head = document.getElementsByTagName("head")[0];
-	let session_id = head.getAttribute("session");
 	let website_url = head.getAttribute("website_url");
-	
-	if (session_id == null) {
-		console.log("session_id not found");
-		return;
-	}
 
+	// The session cookie identifies us, so the hello carries no session id.
 	let socket = new WebSocket(`${website_url}/ws`);
+	let fell_back = false;
 	socket.addEventListener('open', () => {
-		socket.send(`session-id;${session_id}`);
+		socket.send(JSON.stringify({
+			v: PROTOCOL_VERSION,
+			type: 'hello'
+		}));
 	});
 
-	socket.addEventListener('message', (msg) => {
-		if (msg.type == "message") {
-			let data = msg.data;
-			if (typeof data == 'string' || data instanceof String) {
-				console.log(data);
+	// Some proxies break websockets, the event stream carries the same
+	// messages over plain HTTP.
+	socket.addEventListener('close', () => {
+		if (fell_back) {
+			return;
+		}
+
+		fell_back = true;
+		console.log("Socket closed, falling back to the event stream");
+		let events = new EventSource(`${website_url}/events`);
+		events.addEventListener('message', (msg) => handle_message(msg.data));
+	});
+
+	socket.addEventListener('message', (msg) => handle_message(msg.data));
+
+	let cancel = document.getElementById('cancel');
+	cancel.addEventListener('click', async () => {
+		let job_id = cancel.getAttribute('job');
+		if (job_id == null) {
+			return;
+		}
+
+		let response = await fetch(`/api/jobs/${job_id}/cancel`, { method: 'POST' });
+		if (!response.ok) {
+			show_status(await response.text(), false);
+		}
+	});
 
-				if (data.startsWith('job-completed;')) {
-					let id = data.split(';')[1];
-					window.location.href = `files/${id}`;
-				} else if (data.startsWith('job_failed;')) {
-					console.log('Something went wrong!');
-				}
-			}
+	let retry = document.getElementById('retry');
+	retry.addEventListener('click', async () => {
+		let job_id = retry.getAttribute('job');
+		if (job_id == null) {
+			return;
+		}
 
+		hide_button('retry', job_id);
+		let response = await fetch(`/api/jobs/${job_id}/retry`, { method: 'POST' });
+		if (!response.ok) {
+			show_status(await response.text(), false);
 		}
 	});
 });
+
+function handle_message(data) {
+	if (typeof data != 'string') {
+		return;
+	}
+
+	let message;
+	try {
+		message = JSON.parse(data);
+	} catch (err) {
+		console.log(`Recieved malformed message: ${data}`);
+		return;
+	}
+
+	console.log(message);
+	if (message.v != PROTOCOL_VERSION) {
+		console.log(`Unsupported protocol version ${message.v}`);
+		return;
+	}
+
+	switch (message.type) {
+		case 'job-queued':
+			show_status(`${message.file_name} is queued for conversion...`, true);
+			show_button('cancel', message.job_id);
+			hide_button('retry', message.job_id);
+			break;
+		case 'job-waiting':
+			show_status(`Your file is number ${message.position} in the queue...`, true);
+			show_button('cancel', message.job_id);
+			break;
+		case 'job-progress':
+			show_status(`Your file is ${message.stage}...`, true);
+			show_button('cancel', message.job_id);
+			break;
+		case 'job-completed':
+			window.location.href = `/files/${message.file_id}`;
+			break;
+		case 'job-failed':
+			show_status(message.reason, false);
+			hide_button('cancel', message.job_id);
+			show_button('retry', message.job_id);
+			break;
+		case 'job-cancelled':
+			show_status("Your conversion was cancelled.", false);
+			hide_button('cancel', message.job_id);
+			break;
+		case 'error':
+			console.log(`Socket error: ${message.reason}`);
+			break;
+	}
+}
+
+// The cancel and retry buttons always act on the job we last heard about.
+function show_button(id, job_id) {
+	let button = document.getElementById(id);
+	if (button == null) {
+		return;
+	}
+
+	button.setAttribute('job', job_id);
+	button.hidden = false;
+}
+
+function hide_button(id, job_id) {
+	let button = document.getElementById(id);
+	if (button == null || button.getAttribute('job') != job_id) {
+		return;
+	}
+
+	button.removeAttribute('job');
+	button.hidden = true;
+}
+
+function show_status(text, ok) {
+	let status = document.getElementById('status');
+	let status_message = document.getElementById('status-message');
+	if (status == null || status_message == null) {
+		return;
+	}
+
+	status_message.textContent = text;
+	status.style.backgroundColor = ok ? "var(--success-color)" : "var(--error-color)";
+	status.style.display = "block";
+	status.style.visibility = "visible";
+}